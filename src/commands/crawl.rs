@@ -1,22 +1,50 @@
-use crate::chromedriver::{start_chromedriver, ChromeDriverProcess};
 use crate::commands::DataDirConfig;
-use crate::weibo::post::Post;
-use crate::weibo::raw::RawPost;
+use crate::crawler::{
+    fill_long_text, CrawledPost, HttpWeiboClient, LoginConfig, PostList, WeiboClient, WeiboCrawler,
+};
+use crate::storage::Storage;
+use crate::weibo::post::{weibo_timezone, Post, PostSource};
+use chrono::{TimeZone, Utc};
 use log::info;
-use serde::Deserialize;
-use std::thread::sleep;
+use std::collections::HashSet;
 use std::time::Duration;
-use thirtyfour::prelude::*;
 
 #[derive(Debug, clap::Parser)]
 pub struct Config {
     #[clap(flatten)]
     data_dir_config: DataDirConfig,
 
+    /// 抓取的来源: favorites(收藏)、mine(自己发布的微博)、likes(赞过的微博)、
+    /// user:<uid>(某个用户公开的微博)。mine 和 likes 需要先以 weise settings set uid=<uid> 设置自己的 uid
+    #[clap(long, default_value = "favorites")]
+    source: PostSource,
     #[clap(long, default_value = "1")]
     start_page: u32,
     #[clap(long)]
     end_page: Option<u32>,
+    /// 逐页抓取，直到某一页的微博都已抓取过，或者没有更多微博。
+    /// 未指定 --end-page 且未设置 max_page(只用于收藏)时，默认即是此模式
+    #[clap(long, conflicts_with = "end-page")]
+    incremental: bool,
+    /// 从第一页抓取到最后一页，不因遇到已抓取过的页而停止。
+    /// 抓取收藏时，完成后不在收藏中的微博标记为已取消收藏(仍然保留)
+    #[clap(long, conflicts_with_all = &["start-page", "end-page", "incremental"])]
+    full: bool,
+    /// 不抓取微博，只为已保存的长微博补充全文
+    #[clap(long, conflicts_with_all = &["end-page", "incremental", "full"])]
+    fill_long_text: bool,
+    /// 等待登录完成的最长时间(秒)
+    #[clap(long, default_value = "120")]
+    login_timeout: u64,
+    /// webdriver 需要 chromedriver，支持扫码登录；http 只使用已保存的 cookie
+    #[clap(long, arg_enum, default_value = "webdriver")]
+    backend: Backend,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+enum Backend {
+    Webdriver,
+    Http,
 }
 
 pub async fn command(config: Config) -> Result<(), anyhow::Error> {
@@ -24,95 +52,488 @@ pub async fn command(config: Config) -> Result<(), anyhow::Error> {
     let storage = config.data_dir_config.storage()?;
 
     let end_page = match config.end_page {
-        Some(end_page) => end_page,
-        None => storage
+        Some(end_page) => Some(end_page),
+        None if config.incremental || config.full => None,
+        None if config.source != PostSource::Favorites => None,
+        None => storage.settings().get_max_page()?,
+    };
+    let source = config.source.tag();
+    match end_page {
+        Some(end_page) => info!(
+            "crawl {} from page={} to {}",
+            source, config.start_page, end_page
+        ),
+        None if config.full => info!("crawl all pages of {}", source),
+        None => info!(
+            "crawl {} from page={} until no new posts",
+            source, config.start_page
+        ),
+    }
+
+    let login_config = LoginConfig {
+        timeout: Duration::from_secs(config.login_timeout),
+        saved_cookies: storage
             .settings()
-            .get_max_page()?
-            .expect("max_page not set"),
+            .get_session_cookies()?
+            .unwrap_or_default(),
+        ..LoginConfig::default()
+    };
+    let weibo_client: Box<dyn WeiboCrawler> = match config.backend {
+        Backend::Webdriver => Box::new(WeiboClient::login(&login_config).await?),
+        Backend::Http => Box::new(HttpWeiboClient::login(&login_config).await?),
     };
-    info!("crawl from page={} to {}", config.start_page, end_page);
+    storage
+        .settings()
+        .set_session_cookies(&weibo_client.session_cookies().await?)?;
+
+    if config.fill_long_text {
+        backfill_long_text(weibo_client.as_ref(), &storage).await?;
+    } else {
+        crawl_pages(
+            weibo_client.as_ref(),
+            &storage,
+            config.source,
+            config.start_page,
+            end_page,
+            config.full,
+        )
+        .await?;
+    }
+
+    weibo_client.close().await?;
+    Ok(())
+}
+
+// end_page 为 None 时，一直抓取到某一页的微博都已存在于 storage 中，或者没有更多微博为止。
+// full 为 true 时，则一直抓取到没有更多微博为止。
+// 从第一页抓取到没有更多微博，即是一次完整的同步，此时不在收藏中的微博标记为已取消收藏
+async fn crawl_pages(
+    weibo_client: &dyn WeiboCrawler,
+    storage: &Storage,
+    source: PostSource,
+    start_page: u32,
+    end_page: Option<u32>,
+    full: bool,
+) -> Result<(), anyhow::Error> {
+    let list = post_list(storage, source)?;
+    let is_favorites = source == PostSource::Favorites;
+    let now = weibo_timezone().timestamp(Utc::now().timestamp(), 0);
+    let mut faved_ids = HashSet::new();
+    let mut unavailable_ids = storage.posts().long_text_unavailable_ids()?;
+    let mut reached_end = false;
+    let mut page_id = start_page;
+    while end_page.is_none_or(|end_page| page_id <= end_page) {
+        // 接口返回失败(比如登录态失效)时返回错误，不会被当作空页，所以空页即是已到最后一页
+        let posts = get_posts_by_page_with_retry(weibo_client, list, page_id, 3).await?;
+        if posts.is_empty() {
+            info!("page={}, no more posts", page_id);
+            reached_end = true;
+            break;
+        }
 
-    let weibo_client = WeiboClient::login().await?;
-    for page_id in config.start_page..=end_page {
-        let posts = get_favs_by_page_with_retry(&weibo_client, page_id, 3).await?;
         let mut valid_posts = vec![];
-        for p in posts {
+        let mut raw_posts = vec![];
+        for CrawledPost { post: p, raw } in posts {
+            faved_ids.insert(p.id);
             if p.is_valid() {
+                raw_posts.push((p.id, raw));
                 valid_posts.push(p);
             } else {
                 info!("invalid post, id: {}, url: {}", p.id, p.url());
             }
         }
-        info!("page={}, valid post count: {}", page_id, valid_posts.len());
+
+        let mut new_post_count = 0;
+        let mut missing_ids = vec![];
+        for p in &mut valid_posts {
+            if !storage.posts().has_source(p.id, source)? {
+                new_post_count += 1;
+            }
+            complete_long_text(weibo_client, storage, p, &unavailable_ids, &mut missing_ids)
+                .await?;
+        }
+        info!(
+            "page={}, valid post count: {}, new post count: {}",
+            page_id,
+            valid_posts.len(),
+            new_post_count
+        );
         storage.posts().batch_add(&valid_posts)?;
+        storage
+            .raw_posts()
+            .batch_add(raw_posts.iter().map(|(id, raw)| (*id, raw)))?;
+        let post_ids: Vec<i64> = valid_posts.iter().map(|p| p.id).collect();
+        if is_favorites {
+            storage.posts().mark_faved(&post_ids, now)?;
+        }
+        storage.posts().add_source(&post_ids, source)?;
+        if !missing_ids.is_empty() {
+            storage.posts().mark_long_text_unavailable(&missing_ids)?;
+            unavailable_ids.extend(missing_ids);
+        }
+
+        if !full && end_page.is_none() && !valid_posts.is_empty() && new_post_count == 0 {
+            info!("page={}, no new posts, stop crawling", page_id);
+            break;
+        }
+        page_id += 1;
     }
 
-    weibo_client.close().await?;
+    // 一条收藏都没有抓取到时，多半是接口出了问题，不标记
+    if is_favorites && start_page == 1 && reached_end && !faved_ids.is_empty() {
+        let count = storage.posts().mark_unfaved_except(&faved_ids, now)?;
+        info!("{} posts are no longer in favorites", count);
+    }
+    Ok(())
+}
+
+// 长微博的全文，优先沿用 storage 中已有的，否则请求接口获取。
+// 获取失败时不中断抓取，之后可以用 --fill-long-text 补充。已知没有全文的微博不再请求
+async fn complete_long_text(
+    weibo_client: &dyn WeiboCrawler,
+    storage: &Storage,
+    post: &mut Post,
+    unavailable_ids: &HashSet<i64>,
+    missing_ids: &mut Vec<i64>,
+) -> Result<(), anyhow::Error> {
+    if !post.needs_long_text() {
+        return Ok(());
+    }
+    if let Some(stored_post) = storage.posts().get_by_id(post.id)? {
+        if post.long_text.is_none() {
+            post.long_text = stored_post.long_text;
+        }
+        if let (Some(retweeted_post), Some(stored_retweeted_post)) =
+            (&mut post.retweeted_post, stored_post.retweeted_post)
+        {
+            if retweeted_post.long_text.is_none() {
+                retweeted_post.long_text = stored_retweeted_post.long_text;
+            }
+        }
+    }
+    fill_long_text(weibo_client, post, unavailable_ids, missing_ids).await;
     Ok(())
 }
 
-async fn get_favs_by_page_with_retry(
-    weibo_client: &WeiboClient,
+async fn backfill_long_text(
+    weibo_client: &dyn WeiboCrawler,
+    storage: &Storage,
+) -> Result<(), anyhow::Error> {
+    let mut unavailable_ids = storage.posts().long_text_unavailable_ids()?;
+    let limit = 1000;
+    let mut post_id = 0;
+    loop {
+        let posts = storage.posts().get_posts(post_id, limit)?;
+        let should_continue = posts.len() == limit;
+        if should_continue {
+            post_id = posts[posts.len() - 1].id;
+        }
+
+        let mut filled_posts = vec![];
+        let mut missing_ids = vec![];
+        for mut post in posts.into_iter().filter(|p| p.needs_long_text()) {
+            if fill_long_text(weibo_client, &mut post, &unavailable_ids, &mut missing_ids).await {
+                filled_posts.push(post);
+            }
+        }
+        if !filled_posts.is_empty() {
+            storage.posts().batch_add(&filled_posts)?;
+            info!("filled long text for {} posts", filled_posts.len());
+        }
+        if !missing_ids.is_empty() {
+            storage.posts().mark_long_text_unavailable(&missing_ids)?;
+            info!("long text unavailable for {} posts", missing_ids.len());
+            unavailable_ids.extend(missing_ids);
+        }
+
+        if !should_continue {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// mine 和 likes 需要登录用户的 uid
+fn post_list(storage: &Storage, source: PostSource) -> Result<PostList, anyhow::Error> {
+    let own_uid = || {
+        storage.settings().get_uid()?.ok_or_else(|| {
+            anyhow::format_err!(
+                "uid of the logged-in user is unknown, set it with `weise settings set uid=<uid>`"
+            )
+        })
+    };
+    let list = match source {
+        PostSource::Favorites => PostList::Favorites,
+        PostSource::Mine => PostList::Timeline(own_uid()?),
+        PostSource::Likes => PostList::Likes(own_uid()?),
+        PostSource::User(uid) => PostList::Timeline(uid),
+    };
+    Ok(list)
+}
+
+async fn get_posts_by_page_with_retry(
+    weibo_client: &dyn WeiboCrawler,
+    list: PostList,
     page_id: u32,
     retries: u32,
-) -> Result<Vec<Post>, anyhow::Error> {
+) -> Result<Vec<CrawledPost>, anyhow::Error> {
     for i in 0..retries {
-        match weibo_client.get_favs_by_page(page_id).await {
+        match weibo_client.get_posts_by_page(list, page_id).await {
             Ok(posts) => return Ok(posts),
-            Err(e) if i == retries - 1 => return Err(e.into()),
+            Err(e) if i == retries - 1 => return Err(e),
             _ => continue,
         }
     }
     unreachable!()
 }
 
-pub struct WeiboClient {
-    #[allow(unused)]
-    chromedriver: ChromeDriverProcess,
-    driver: WebDriver,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawler::SessionCookie;
+    use crate::test_util::{FakeHttpServer, FakeRequest, FakeResponse};
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
 
-impl WeiboClient {
-    pub async fn login() -> Result<WeiboClient, anyhow::Error> {
-        let chromedriver = start_chromedriver(4444)?;
-        let cap = DesiredCapabilities::chrome();
-        let driver = WebDriver::new(&chromedriver.server_url(), cap).await?;
-        driver.goto("https://weibo.com/").await?;
-        // TODO: 改为等待登录成功
-        sleep(Duration::from_secs(20));
-
-        Ok(WeiboClient {
-            chromedriver,
-            driver,
-        })
+    fn fixture(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn page_param(path: &str) -> u32 {
+        path.split(['?', '&'])
+            .find_map(|kv| kv.strip_prefix("page="))
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    // 模拟收藏接口，pages[i] 为第 i + 1 页的微博；同时记录请求过的页码
+    fn fake_weibo(pages: Vec<Vec<Value>>) -> (FakeHttpServer, Arc<Mutex<Vec<u32>>>) {
+        let requested_pages = Arc::new(Mutex::new(vec![]));
+        let requested = requested_pages.clone();
+        let server = FakeHttpServer::start(move |req: &FakeRequest| {
+            if req.path.starts_with("/ajax/statuses/longtext") {
+                return FakeResponse::json(json!({"ok": 1, "data": {}}));
+            }
+            let page_id = page_param(&req.path);
+            requested.lock().unwrap().push(page_id);
+            let data = pages.get(page_id as usize - 1).cloned().unwrap_or_default();
+            FakeResponse::json(json!({"ok": 1, "data": data}))
+        });
+        (server, requested_pages)
     }
 
-    pub async fn close(self) -> Result<(), anyhow::Error> {
-        self.driver.quit().await?;
+    #[tokio::test]
+    async fn test_crawl_until_no_new_posts() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        let cookies = SessionCookie::parse_header("SUB=abc")?;
+        let text = fixture(include_str!("../../test_data/text.json"));
+        let video = fixture(include_str!("../../test_data/video.json"));
+        let picture = fixture(include_str!("../../test_data/picture.json"));
+
+        // 第一次抓取，直到返回空页为止
+        let (server, requested_pages) = fake_weibo(vec![vec![text.clone()], vec![video.clone()]]);
+        let client = HttpWeiboClient::connect(&server.url(), &cookies).await?;
+        requested_pages.lock().unwrap().clear();
+        crawl_pages(&client, &storage, PostSource::Favorites, 1, None, false).await?;
+        assert_eq!(*requested_pages.lock().unwrap(), vec![1, 2, 3]);
+        assert_eq!(storage.posts().get_posts(0, 10)?.len(), 2);
+
+        // 有新收藏时，遇到全部已抓取过的一页即停止
+        let (server, requested_pages) = fake_weibo(vec![vec![picture], vec![text], vec![video]]);
+        let client = HttpWeiboClient::connect(&server.url(), &cookies).await?;
+        requested_pages.lock().unwrap().clear();
+        crawl_pages(&client, &storage, PostSource::Favorites, 1, None, false).await?;
+        assert_eq!(*requested_pages.lock().unwrap(), vec![1, 2]);
+        assert_eq!(storage.posts().get_posts(0, 10)?.len(), 3);
+        assert_eq!(storage.raw_posts().count()?, 3);
         Ok(())
     }
 
-    pub async fn get_favs_by_page(&self, page_id: u32) -> Result<Vec<Post>, anyhow::Error> {
-        self.driver
-            .goto(format!(
-                "https://weibo.com/ajax/favorites/all_fav?page={}",
-                page_id
-            ))
-            .await?;
-
-        let content = self.driver.find(By::Css("pre")).await?.text().await?;
-        let mut posts = vec![];
-        let res: FavResponse = serde_json::from_str(&content)?;
-        for rp in res.data {
-            let p = rp.normalize();
-            posts.push(p);
-        }
-        Ok(posts)
+    #[tokio::test]
+    async fn test_full_crawl_marks_unfaved() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        let cookies = SessionCookie::parse_header("SUB=abc")?;
+        let text = fixture(include_str!("../../test_data/text.json"));
+        let video = fixture(include_str!("../../test_data/video.json"));
+        let video_id = video["id"].as_i64().unwrap();
+
+        let (server, _) = fake_weibo(vec![vec![text.clone()], vec![video.clone()]]);
+        let client = HttpWeiboClient::connect(&server.url(), &cookies).await?;
+        crawl_pages(&client, &storage, PostSource::Favorites, 1, None, true).await?;
+        let posts = storage.posts().get_posts(0, 10)?;
+        assert!(posts
+            .iter()
+            .all(|p| p.faved_at.is_some() && p.unfaved_at.is_none()));
+
+        // 全部页都已抓取过，仍然抓取到最后；不在收藏中的微博标记为已取消收藏，但仍然保留
+        let (server, requested_pages) = fake_weibo(vec![vec![text.clone()], vec![]]);
+        let client = HttpWeiboClient::connect(&server.url(), &cookies).await?;
+        requested_pages.lock().unwrap().clear();
+        crawl_pages(&client, &storage, PostSource::Favorites, 1, None, true).await?;
+        assert_eq!(*requested_pages.lock().unwrap(), vec![1, 2]);
+        let video_post = storage.posts().get_by_id(video_id)?.unwrap();
+        assert!(video_post.unfaved_at.is_some());
+        assert!(storage.posts().exists(video_id)?);
+
+        // 没有抓取到任何收藏时，不做标记
+        let (server, _) = fake_weibo(vec![]);
+        let client = HttpWeiboClient::connect(&server.url(), &cookies).await?;
+        crawl_pages(&client, &storage, PostSource::Favorites, 1, None, true).await?;
+        let text_post = storage.posts().get_by_id(text["id"].as_i64().unwrap())?;
+        assert!(text_post.unwrap().unfaved_at.is_none());
+
+        // 抓取中途登录态失效时，抓取失败，不做标记
+        let page_1 = vec![video.clone()];
+        let server = FakeHttpServer::start(move |req: &FakeRequest| match page_param(&req.path) {
+            1 => FakeResponse::json(json!({"ok": 1, "data": page_1})),
+            _ => FakeResponse::json(json!({"ok": -100, "url": "https://weibo.com/login.php"})),
+        });
+        let client = HttpWeiboClient::connect(&server.url(), &cookies).await?;
+        assert!(
+            crawl_pages(&client, &storage, PostSource::Favorites, 1, None, true)
+                .await
+                .is_err()
+        );
+        let text_post = storage.posts().get_by_id(text["id"].as_i64().unwrap())?;
+        assert!(text_post.unwrap().unfaved_at.is_none());
+
+        // 再次收藏
+        let (server, _) = fake_weibo(vec![vec![video, text]]);
+        let client = HttpWeiboClient::connect(&server.url(), &cookies).await?;
+        crawl_pages(&client, &storage, PostSource::Favorites, 1, None, false).await?;
+        let video_post = storage.posts().get_by_id(video_id)?.unwrap();
+        assert!(video_post.unfaved_at.is_none());
+        Ok(())
     }
-}
 
-#[derive(Deserialize)]
-struct FavResponse {
-    // ok: i32,
-    data: Vec<RawPost>,
+    #[tokio::test]
+    async fn test_crawl_other_sources() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        let cookies = SessionCookie::parse_header("SUB=abc")?;
+        let text = fixture(include_str!("../../test_data/text.json"));
+        let video = fixture(include_str!("../../test_data/video.json"));
+        let text_id = text["id"].as_i64().unwrap();
+        let video_id = video["id"].as_i64().unwrap();
+
+        // 时间线和赞过的微博的接口，微博在 data.list 中
+        let server = FakeHttpServer::start(move |req: &FakeRequest| {
+            let data = match (req.path.split('?').next().unwrap(), page_param(&req.path)) {
+                ("/ajax/favorites/all_fav", 1) => vec![text.clone()],
+                ("/ajax/statuses/likelist", 1) if req.path.contains("uid=42&") => {
+                    vec![text.clone(), video.clone()]
+                }
+                ("/ajax/statuses/mymblog", 1) if req.path.contains("uid=7&") => {
+                    vec![video.clone()]
+                }
+                _ => vec![],
+            };
+            FakeResponse::json(json!({"ok": 1, "data": {"list": data}}))
+        });
+        let client = HttpWeiboClient::connect(&server.url(), &cookies).await?;
+
+        // 不知道自己的 uid 时，无法抓取赞过的微博
+        assert!(
+            crawl_pages(&client, &storage, PostSource::Likes, 1, None, false)
+                .await
+                .is_err()
+        );
+        storage.settings().set_uid(42)?;
+        crawl_pages(&client, &storage, PostSource::Favorites, 1, None, false).await?;
+        crawl_pages(&client, &storage, PostSource::Likes, 1, None, false).await?;
+        crawl_pages(&client, &storage, PostSource::User(7), 1, None, true).await?;
+
+        let text_post = storage.posts().get_by_id(text_id)?.unwrap();
+        assert_eq!(
+            text_post.sources,
+            vec![PostSource::Favorites, PostSource::Likes]
+        );
+        let video_post = storage.posts().get_by_id(video_id)?.unwrap();
+        assert_eq!(
+            video_post.sources,
+            vec![PostSource::Likes, PostSource::User(7)]
+        );
+        // 只有收藏记录收藏时间，完整抓取其他来源时也不会标记取消收藏
+        assert!(text_post.faved_at.is_some());
+        assert!(video_post.faved_at.is_none());
+        assert!(text_post.unfaved_at.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_backfill_long_text() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        let cookies = SessionCookie::parse_header("SUB=abc")?;
+        // 此微博及被转发的微博都是长微博，被转发的微博的全文接口没有返回全文
+        let mut raw = fixture(include_str!("../../test_data/text_retweet.json"));
+        raw["isLongText"] = json!(true);
+        let post = CrawledPost::from_raw(raw)?.post;
+        let retweeted_post_id = post.retweeted_post.as_ref().unwrap().id;
+        storage.posts().add(&post)?;
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let requested = requests.clone();
+        let server = FakeHttpServer::start(move |req: &FakeRequest| {
+            if req.path == "/ajax/statuses/longtext?id=L9Rfpgn0d" {
+                requested.lock().unwrap().push(req.path.clone());
+                return FakeResponse::json(fixture(include_str!("../../test_data/long_text.json")));
+            }
+            if req.path.starts_with("/ajax/statuses/longtext") {
+                requested.lock().unwrap().push(req.path.clone());
+                return FakeResponse::json(json!({"ok": 1, "data": {}}));
+            }
+            FakeResponse::json(json!({"ok": 1, "data": []}))
+        });
+        let client = HttpWeiboClient::connect(&server.url(), &cookies).await?;
+
+        backfill_long_text(&client, &storage).await?;
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        let stored = storage.posts().get_by_id(post.id)?.unwrap();
+        assert!(stored.long_text.is_some());
+        assert!(stored.needs_long_text());
+        assert!(storage
+            .posts()
+            .long_text_unavailable_ids()?
+            .contains(&retweeted_post_id));
+
+        // 不再请求没有全文的微博
+        backfill_long_text(&client, &storage).await?;
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_crawl_skips_unavailable_long_text() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        let cookies = SessionCookie::parse_header("SUB=abc")?;
+        let retweet = fixture(include_str!("../../test_data/text_retweet.json"));
+        let retweeted_post_id = retweet["retweeted_status"]["id"].as_i64().unwrap();
+
+        let long_text_requests = Arc::new(Mutex::new(0));
+        let requests = long_text_requests.clone();
+        let server = FakeHttpServer::start(move |req: &FakeRequest| {
+            if req.path.starts_with("/ajax/statuses/longtext") {
+                *requests.lock().unwrap() += 1;
+                return FakeResponse::json(json!({"ok": 1, "data": {}}));
+            }
+            let data = match page_param(&req.path) {
+                1 => vec![retweet.clone()],
+                _ => vec![],
+            };
+            FakeResponse::json(json!({"ok": 1, "data": data}))
+        });
+        let client = HttpWeiboClient::connect(&server.url(), &cookies).await?;
+
+        crawl_pages(&client, &storage, PostSource::Favorites, 1, None, true).await?;
+        assert_eq!(*long_text_requests.lock().unwrap(), 1);
+        assert!(storage
+            .posts()
+            .long_text_unavailable_ids()?
+            .contains(&retweeted_post_id));
+
+        // 再次完整抓取时，不再请求已知没有全文的微博
+        crawl_pages(&client, &storage, PostSource::Favorites, 1, None, true).await?;
+        assert_eq!(*long_text_requests.lock().unwrap(), 1);
+        Ok(())
+    }
 }
@@ -0,0 +1,534 @@
+use crate::commands::search::FilterConfig;
+use crate::commands::DataDirConfig;
+use crate::index::{SortOrder, WeiboIndexer, WeiboSearchParams};
+use crate::render::{media_urls, render_post, PAGE_STYLE};
+use crate::storage::Storage;
+use crate::weibo::post::{MediaAsset, Post};
+use log::info;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, clap::Parser)]
+pub struct Config {
+    #[clap(flatten)]
+    data_dir_config: DataDirConfig,
+
+    /// 只导出符合搜索条件的微博，不指定时导出全部
+    #[clap(flatten)]
+    filter: FilterConfig,
+    /// 导出格式。jsonl 为每行一条微博；html 为一个静态页面；markdown 为每条微博一个文件
+    #[clap(long, arg_enum, default_value = "jsonl")]
+    format: ExportFormat,
+    /// jsonl 为输出文件，不指定时输出到 stdout；html 和 markdown 为输出目录，
+    /// 已存档的图片会复制到其中的 media 目录
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+    /// 不导出已取消收藏的微博。默认导出全部存档，包括已取消收藏的微博，不论是否指定了搜索条件
+    #[clap(long)]
+    exclude_unfaved: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+enum ExportFormat {
+    Jsonl,
+    Html,
+    Markdown,
+}
+
+pub async fn command(config: Config) -> Result<(), anyhow::Error> {
+    config.data_dir_config.ensure_data_dir_exists()?;
+    let storage = config.data_dir_config.storage()?;
+
+    let post_ids = if config.filter.is_empty() {
+        None
+    } else {
+        let indexer = config.data_dir_config.weibo_indexer()?;
+        Some(matching_post_ids(&indexer, filter_params(&config.filter))?)
+    };
+
+    let output_dir = || {
+        config
+            .output
+            .clone()
+            .ok_or_else(|| anyhow::format_err!("--output DIR is required for html and markdown"))
+    };
+    let media_dir = config.data_dir_config.media_dir();
+    let mut exporter: Box<dyn Exporter> = match config.format {
+        ExportFormat::Jsonl => {
+            let writer: Box<dyn Write> = match &config.output {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(std::io::stdout()),
+            };
+            Box::new(JsonlExporter::new(writer))
+        }
+        ExportFormat::Html => Box::new(HtmlExporter::create(output_dir()?, media_dir)?),
+        ExportFormat::Markdown => Box::new(MarkdownExporter::create(output_dir()?, media_dir)?),
+    };
+
+    let count = export_posts(
+        &storage,
+        post_ids,
+        !config.exclude_unfaved,
+        exporter.as_mut(),
+    )?;
+    exporter.finish()?;
+    info!("exported {} posts", count);
+    Ok(())
+}
+
+// 已取消收藏的微博是否导出只由 --exclude-unfaved 决定，搜索时总是包括在内
+fn filter_params(filter: &FilterConfig) -> WeiboSearchParams {
+    WeiboSearchParams {
+        include_unfaved: true,
+        ..filter.to_params()
+    }
+}
+
+// 按发布时间顺序，返回索引中符合搜索条件的所有微博
+fn matching_post_ids(
+    indexer: &WeiboIndexer,
+    params: WeiboSearchParams,
+) -> Result<Vec<i64>, anyhow::Error> {
+    let mut params = WeiboSearchParams {
+        sort: SortOrder::Oldest,
+        limit: 1000,
+        ..params
+    };
+    let mut post_ids = vec![];
+    loop {
+        let page = indexer.search(&params)?;
+        post_ids.extend(page.posts.iter().map(|post| post.id));
+        match page.next_cursor {
+            Some(cursor) => params.cursor = Some(cursor),
+            None => break,
+        }
+    }
+    Ok(post_ids)
+}
+
+// post_ids 为 None 时导出全部微博。已加入 tombstone 的微博不导出
+fn export_posts(
+    storage: &Storage,
+    post_ids: Option<Vec<i64>>,
+    include_unfaved: bool,
+    exporter: &mut dyn Exporter,
+) -> Result<usize, anyhow::Error> {
+    let tombstones = storage.post_tombstones().all_post_ids()?;
+    let mut count = 0;
+    let mut export = |post: Post| -> Result<(), anyhow::Error> {
+        if tombstones.contains(&post.id) || (!include_unfaved && post.unfaved_at.is_some()) {
+            return Ok(());
+        }
+        let mut archived_media = HashMap::new();
+        for url in media_urls(&post) {
+            if let Some(path) = storage.media().archived_path(&url)? {
+                archived_media.insert(url, path);
+            }
+        }
+        exporter.export(&post, &archived_media)?;
+        count += 1;
+        Ok(())
+    };
+
+    match post_ids {
+        Some(post_ids) => {
+            for post_id in post_ids {
+                if let Some(post) = storage.posts().get_by_id(post_id)? {
+                    export(post)?;
+                }
+            }
+        }
+        None => {
+            let limit = 1000;
+            let mut post_id = 0;
+            loop {
+                let posts = storage.posts().get_posts(post_id, limit)?;
+                let should_continue = posts.len() == limit;
+                if let Some(last) = posts.last() {
+                    post_id = last.id;
+                }
+                for post in posts {
+                    export(post)?;
+                }
+                if !should_continue {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(count)
+}
+
+trait Exporter {
+    // archived_media 为已存档的图片: 原 url -> media 目录中的相对路径
+    fn export(
+        &mut self,
+        post: &Post,
+        archived_media: &HashMap<String, String>,
+    ) -> Result<(), anyhow::Error>;
+
+    fn finish(&mut self) -> Result<(), anyhow::Error>;
+}
+
+struct JsonlExporter {
+    writer: BufWriter<Box<dyn Write>>,
+}
+
+impl JsonlExporter {
+    fn new(writer: Box<dyn Write>) -> JsonlExporter {
+        JsonlExporter {
+            writer: BufWriter::new(writer),
+        }
+    }
+}
+
+impl Exporter for JsonlExporter {
+    fn export(&mut self, post: &Post, _: &HashMap<String, String>) -> Result<(), anyhow::Error> {
+        serde_json::to_writer(&mut self.writer, post)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), anyhow::Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// 把已存档的图片复制到输出目录的 media 目录中，导出的文件以相对路径引用，可以整个目录拷走
+struct MediaCopier {
+    media_dir: PathBuf,
+    output_dir: PathBuf,
+}
+
+impl MediaCopier {
+    // 返回原 url -> 相对于输出目录的路径
+    fn copy(
+        &self,
+        archived_media: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, anyhow::Error> {
+        let mut links = HashMap::new();
+        for (url, path) in archived_media {
+            let src = self.media_dir.join(path);
+            if !src.exists() {
+                continue;
+            }
+            let dst = self.output_dir.join("media").join(path);
+            if !dst.exists() {
+                fs::create_dir_all(dst.parent().unwrap())?;
+                fs::copy(&src, &dst)?;
+            }
+            links.insert(url.clone(), format!("media/{}", path));
+        }
+        Ok(links)
+    }
+}
+
+// 所有微博在一个 index.html 中
+struct HtmlExporter {
+    writer: BufWriter<File>,
+    media: MediaCopier,
+}
+
+impl HtmlExporter {
+    fn create<P: AsRef<Path>>(output_dir: P, media_dir: P) -> Result<HtmlExporter, anyhow::Error> {
+        let output_dir = output_dir.as_ref();
+        fs::create_dir_all(output_dir)?;
+        let mut writer = BufWriter::new(File::create(output_dir.join("index.html"))?);
+        write!(
+            writer,
+            r#"<!DOCTYPE html>
+<html lang="zh-CN">
+<head><meta charset="utf-8"><title>weise</title><style>{}</style></head>
+<body>
+"#,
+            PAGE_STYLE
+        )?;
+        Ok(HtmlExporter {
+            writer,
+            media: MediaCopier {
+                media_dir: media_dir.as_ref().to_path_buf(),
+                output_dir: output_dir.to_path_buf(),
+            },
+        })
+    }
+}
+
+impl Exporter for HtmlExporter {
+    fn export(
+        &mut self,
+        post: &Post,
+        archived_media: &HashMap<String, String>,
+    ) -> Result<(), anyhow::Error> {
+        let links = self.media.copy(archived_media)?;
+        let mut html = String::from(r#"<article class="post">"#);
+        render_post(&mut html, post, &links);
+        html.push_str("</article>\n");
+        self.writer.write_all(html.as_bytes())?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), anyhow::Error> {
+        self.writer.write_all(b"</body>\n</html>\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// 每条微博一个 {id}.md 文件，开头是 YAML front matter
+struct MarkdownExporter {
+    output_dir: PathBuf,
+    media: MediaCopier,
+}
+
+impl MarkdownExporter {
+    fn create<P: AsRef<Path>>(
+        output_dir: P,
+        media_dir: P,
+    ) -> Result<MarkdownExporter, anyhow::Error> {
+        let output_dir = output_dir.as_ref();
+        fs::create_dir_all(output_dir)?;
+        Ok(MarkdownExporter {
+            output_dir: output_dir.to_path_buf(),
+            media: MediaCopier {
+                media_dir: media_dir.as_ref().to_path_buf(),
+                output_dir: output_dir.to_path_buf(),
+            },
+        })
+    }
+}
+
+impl Exporter for MarkdownExporter {
+    fn export(
+        &mut self,
+        post: &Post,
+        archived_media: &HashMap<String, String>,
+    ) -> Result<(), anyhow::Error> {
+        let links = self.media.copy(archived_media)?;
+        let path = self.output_dir.join(format!("{}.md", post.id));
+        fs::write(path, render_markdown(post, &links))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+fn render_markdown(post: &Post, links: &HashMap<String, String>) -> String {
+    // JSON 的字符串也是合法的 YAML 字符串，省去转义的麻烦
+    let quote = |s: &str| serde_json::to_string(s).unwrap();
+    let link = |url: &str| links.get(url).cloned().unwrap_or_else(|| url.to_string());
+
+    let mut md = String::from("---\n");
+    md.push_str(&format!("id: {}\n", post.id));
+    md.push_str(&format!("url: {}\n", quote(&post.url())));
+    md.push_str(&format!("author: {}\n", quote(&post.user.screen_name)));
+    md.push_str(&format!("created_at: {}\n", post.created_at.to_rfc3339()));
+    if !post.is_visible() {
+        md.push_str(&format!("visibility: {}\n", quote(post.visibility.label())));
+    }
+    if let Some(retweeted_post) = &post.retweeted_post {
+        md.push_str(&format!(
+            "retweeted_url: {}\n",
+            quote(&retweeted_post.url())
+        ));
+        md.push_str(&format!(
+            "retweeted_author: {}\n",
+            quote(&retweeted_post.user.screen_name)
+        ));
+    }
+    let media = media_urls(post);
+    if !media.is_empty() {
+        md.push_str("media:\n");
+        for url in media {
+            md.push_str(&format!("  - {}\n", quote(&link(&url))));
+        }
+    }
+    md.push_str("---\n\n");
+
+    render_markdown_body(&mut md, post, &link);
+    if let Some(retweeted_post) = &post.retweeted_post {
+        let mut quoted = format!(
+            "**@{}** [原微博]({})\n\n",
+            retweeted_post.user.screen_name,
+            retweeted_post.url()
+        );
+        render_markdown_body(&mut quoted, retweeted_post, &link);
+        md.push('\n');
+        for line in quoted.trim_end().lines() {
+            // 空行只保留 >，不留行尾空格
+            let line = format!("> {}", line);
+            md.push_str(line.trim_end());
+            md.push('\n');
+        }
+    }
+    md
+}
+
+fn render_markdown_body<F: Fn(&str) -> String>(md: &mut String, post: &Post, link: &F) {
+    md.push_str(post.full_text().trim_end());
+    md.push('\n');
+    match &post.media_asset {
+        MediaAsset::None => {}
+        MediaAsset::Pictures(picture_urls) => {
+            md.push('\n');
+            for url in picture_urls {
+                md.push_str(&format!("![]({})\n", link(url)));
+            }
+        }
+        MediaAsset::Video(video) => {
+            md.push_str(&format!(
+                "\n[![视频 {}:{:02}]({})]({})\n",
+                video.duration_secs / 60,
+                video.duration_secs % 60,
+                link(&video.cover_picture_url),
+                video.url
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{load_post, temp_dir};
+    use crate::weibo::post::{weibo_timezone, PostSource};
+    use chrono::TimeZone;
+    use clap::Parser;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_export() -> Result<(), anyhow::Error> {
+        let dir = temp_dir("export");
+        let media_dir = dir.join("media");
+        fs::create_dir_all(media_dir.join("ab"))?;
+        fs::write(media_dir.join("ab/abc.jpg"), b"picture")?;
+
+        let storage = Storage::open(":memory:")?;
+        let text_post = load_post(include_str!("../../test_data/text.json"));
+        let picture_retweet = load_post(include_str!("../../test_data/picture_retweet.json"));
+        let video_post = load_post(include_str!("../../test_data/video.json"));
+        storage.posts().batch_add(&[
+            text_post.clone(),
+            picture_retweet.clone(),
+            video_post.clone(),
+        ])?;
+        storage.post_tombstones().add(&video_post)?;
+        let picture_url = match &picture_retweet.retweeted_post.as_ref().unwrap().media_asset {
+            MediaAsset::Pictures(urls) => urls[0].clone(),
+            _ => unreachable!(),
+        };
+        storage
+            .media()
+            .set_archived(&picture_url, "abc", "ab/abc.jpg")?;
+
+        // jsonl
+        let jsonl_path = dir.join("posts.jsonl");
+        let mut exporter = JsonlExporter::new(Box::new(File::create(&jsonl_path)?));
+        assert_eq!(export_posts(&storage, None, true, &mut exporter)?, 2);
+        exporter.finish()?;
+        let lines: Vec<Post> = fs::read_to_string(&jsonl_path)?
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|post| post.id != video_post.id));
+
+        // html
+        let html_dir = dir.join("html");
+        let mut exporter = HtmlExporter::create(&html_dir, &media_dir)?;
+        export_posts(&storage, None, true, &mut exporter)?;
+        exporter.finish()?;
+        let html = fs::read_to_string(html_dir.join("index.html"))?;
+        assert!(html.contains(&text_post.url()));
+        assert!(!html.contains(&video_post.url()));
+        assert!(html.contains(r#"<blockquote class="retweet">"#));
+        assert!(html.contains(r#"src="media/ab/abc.jpg""#));
+        assert!(html.ends_with("</html>\n"));
+        assert_eq!(fs::read(html_dir.join("media/ab/abc.jpg"))?, b"picture");
+
+        // markdown，只导出指定的微博
+        let md_dir = dir.join("markdown");
+        let mut exporter = MarkdownExporter::create(&md_dir, &media_dir)?;
+        let post_ids = Some(vec![picture_retweet.id, video_post.id]);
+        assert_eq!(export_posts(&storage, post_ids, true, &mut exporter)?, 1);
+        let md = fs::read_to_string(md_dir.join(format!("{}.md", picture_retweet.id)))?;
+        assert!(md.starts_with(&format!("---\nid: {}\n", picture_retweet.id)));
+        assert!(md.contains(&format!("author: {:?}\n", picture_retweet.user.screen_name)));
+        assert!(md.contains("media:\n  - \"media/ab/abc.jpg\"\n"));
+        assert!(md.contains("> ![](media/ab/abc.jpg)\n"));
+        assert!(md.contains("> **@网路冷眼**"));
+        assert!(!md_dir.join(format!("{}.md", text_post.id)).exists());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_matching_post_ids() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let text_post = load_post(include_str!("../../test_data/text.json"));
+        let video_post = load_post(include_str!("../../test_data/video.json"));
+        let picture_post = load_post(include_str!("../../test_data/picture.json"));
+        indexer.index_weibo_posts(&[text_post.clone(), video_post, picture_post.clone()])?;
+
+        let params = WeiboSearchParams {
+            user: Some(text_post.user.screen_name.clone()),
+            ..Default::default()
+        };
+        assert_eq!(matching_post_ids(&indexer, params)?, vec![text_post.id]);
+
+        // 按发布时间顺序
+        let ids = matching_post_ids(&indexer, WeiboSearchParams::default())?;
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids[0], picture_post.id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_unfaved_with_filter() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        let text_post = load_post(include_str!("../../test_data/text.json"));
+        let video_post = load_post(include_str!("../../test_data/video.json"));
+        storage
+            .posts()
+            .batch_add(&[text_post.clone(), video_post.clone()])?;
+        let ids = [text_post.id, video_post.id];
+        storage.posts().add_source(&ids, PostSource::Favorites)?;
+        let now = weibo_timezone().ymd(2022, 2, 1).and_hms(0, 0, 0);
+        storage
+            .posts()
+            .mark_unfaved_except(&HashSet::from([video_post.id]), now)?;
+        let indexer = WeiboIndexer::in_ram();
+        indexer.index_weibo_posts(&storage.posts().get_posts(0, 10)?)?;
+
+        // 指定搜索条件时，仍然导出已取消收藏的微博
+        let filter =
+            FilterConfig::parse_from(["export", "--user", text_post.user.screen_name.as_str()]);
+        let post_ids = matching_post_ids(&indexer, filter_params(&filter))?;
+        assert_eq!(post_ids, vec![text_post.id]);
+
+        assert!(storage
+            .posts()
+            .get_by_id(text_post.id)?
+            .unwrap()
+            .unfaved_at
+            .is_some());
+        let mut exporter = JsonlExporter::new(Box::new(std::io::sink()));
+        assert_eq!(
+            export_posts(&storage, Some(post_ids.clone()), true, &mut exporter)?,
+            1
+        );
+
+        // --exclude-unfaved
+        assert_eq!(
+            export_posts(&storage, Some(post_ids), false, &mut exporter)?,
+            0
+        );
+        assert_eq!(export_posts(&storage, None, false, &mut exporter)?, 1);
+        Ok(())
+    }
+}
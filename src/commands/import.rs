@@ -0,0 +1,193 @@
+use crate::commands::DataDirConfig;
+use crate::crawler::{CrawledPost, PostListResponse};
+use crate::storage::Storage;
+use crate::weibo::post::PostSource;
+use log::{info, warn};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, clap::Parser)]
+pub struct Config {
+    #[clap(flatten)]
+    data_dir_config: DataDirConfig,
+
+    /// JSON 文件，或者包含 JSON 文件的目录。文件内容可以是收藏等接口的返回、单条微博或者微博的数组
+    #[clap(required = true)]
+    paths: Vec<PathBuf>,
+    /// 导入的微博的来源，比如导入自己的微博时为 mine，见 weise crawl --source
+    #[clap(long, default_value = "favorites")]
+    source: PostSource,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct ImportStats {
+    files: usize,
+    failed_files: usize,
+    posts: usize,
+    invalid_posts: usize,
+}
+
+pub async fn command(config: Config) -> Result<(), anyhow::Error> {
+    config.data_dir_config.ensure_data_dir_exists()?;
+    let storage = config.data_dir_config.storage()?;
+
+    let stats = import_paths(&storage, &config.paths, config.source)?;
+    info!(
+        "imported {} posts from {} files, {} invalid posts, {} files failed",
+        stats.posts, stats.files, stats.invalid_posts, stats.failed_files
+    );
+    if stats.posts > 0 {
+        info!("run `weise crawl --fill-long-text` to fetch full text of long posts, then `weise index`");
+    }
+    Ok(())
+}
+
+// 单个文件出错时只记录下来，继续导入其他文件
+fn import_paths(
+    storage: &Storage,
+    paths: &[PathBuf],
+    source: PostSource,
+) -> Result<ImportStats, anyhow::Error> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            collect_json_files(path, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    let mut stats = ImportStats::default();
+    for file in files {
+        stats.files += 1;
+        let posts = match fs::read_to_string(&file)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| parse_posts(&content))
+        {
+            Ok(posts) => posts,
+            Err(e) => {
+                warn!("{}: {}", file.display(), e);
+                stats.failed_files += 1;
+                continue;
+            }
+        };
+
+        let mut valid_posts = vec![];
+        for fav_post in posts {
+            if fav_post.post.is_valid() {
+                valid_posts.push(fav_post);
+            } else {
+                info!("{}: invalid post, id: {}", file.display(), fav_post.post.id);
+                stats.invalid_posts += 1;
+            }
+        }
+        let posts: Vec<_> = valid_posts.iter().map(|p| p.post.clone()).collect();
+        storage.posts().batch_add(&posts)?;
+        storage
+            .raw_posts()
+            .batch_add(valid_posts.iter().map(|p| (p.post.id, &p.raw)))?;
+        let post_ids: Vec<i64> = posts.iter().map(|p| p.id).collect();
+        storage.posts().add_source(&post_ids, source)?;
+        info!("{}: {} posts", file.display(), valid_posts.len());
+        stats.posts += valid_posts.len();
+    }
+    Ok(stats)
+}
+
+// 目录中的 .json 文件，包括子目录中的，按路径排序
+fn collect_json_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), anyhow::Error> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_json_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "json") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// 根据内容判断格式: 有 data 字段的是微博列表接口(收藏、时间线等)的返回，数组是多条微博，否则是单条微博
+fn parse_posts(content: &str) -> Result<Vec<CrawledPost>, anyhow::Error> {
+    let value: Value = serde_json::from_str(content)?;
+    let posts = match value {
+        Value::Array(values) => values
+            .into_iter()
+            .map(CrawledPost::from_raw)
+            .collect::<Result<_, _>>()?,
+        Value::Object(ref object) if object.contains_key("data") => {
+            let res: PostListResponse = serde_json::from_value(value)?;
+            if !res.is_ok() {
+                return Err(anyhow::format_err!(
+                    "post list response is not ok, probably not logged in"
+                ));
+            }
+            res.into_crawled_posts()?
+        }
+        Value::Object(_) => vec![CrawledPost::from_raw(value)?],
+        _ => return Err(anyhow::format_err!("expected a JSON object or array")),
+    };
+    Ok(posts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn test_import_paths() -> Result<(), anyhow::Error> {
+        let dir = temp_dir("import");
+        fs::create_dir_all(dir.join("sub"))?;
+
+        let text: Value = serde_json::from_str(include_str!("../../test_data/text.json"))?;
+        let video: Value = serde_json::from_str(include_str!("../../test_data/video.json"))?;
+        let picture: Value = serde_json::from_str(include_str!("../../test_data/picture.json"))?;
+        let mut invalid = text.clone();
+        invalid["id"] = 1.into();
+        invalid["user"]["id"] = 0.into();
+
+        fs::write(dir.join("text.json"), text.to_string())?;
+        let fav_response = serde_json::json!({"ok": 1, "data": [video, invalid]});
+        fs::write(dir.join("sub/fav.json"), fav_response.to_string())?;
+        fs::write(
+            dir.join("sub/posts.json"),
+            Value::from(vec![picture]).to_string(),
+        )?;
+        fs::write(
+            dir.join("not_logged_in.json"),
+            r#"{"ok": -100, "data": []}"#,
+        )?;
+        fs::write(dir.join("broken.json"), "{")?;
+        fs::write(dir.join("notes.txt"), "not json")?;
+
+        let storage = Storage::open(":memory:")?;
+        let stats = import_paths(&storage, std::slice::from_ref(&dir), PostSource::Favorites)?;
+        assert_eq!(
+            stats,
+            ImportStats {
+                files: 5,
+                failed_files: 2,
+                posts: 3,
+                invalid_posts: 1,
+            }
+        );
+        assert_eq!(storage.posts().count()?, 3);
+        assert!(!storage.posts().exists(1)?);
+        assert_eq!(storage.raw_posts().count()?, 3);
+        assert!(storage.raw_posts().get(1)?.is_none());
+        let text_post = storage.posts().get_by_id(text["id"].as_i64().unwrap())?;
+        assert_eq!(text_post.unwrap().sources, vec![PostSource::Favorites]);
+
+        // 明确指定的文件，不论扩展名
+        let stats = import_paths(&storage, &[dir.join("notes.txt")], PostSource::Mine)?;
+        assert_eq!(stats.failed_files, 1);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
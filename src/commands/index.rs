@@ -1,26 +1,65 @@
 use crate::commands::DataDirConfig;
+use crate::index::{post_version, WeiboIndexer};
+use crate::storage::Storage;
 use crate::weibo::post::Post;
 use log::info;
 use std::fs;
+use tantivy::TantivyError;
 
 #[derive(Debug, clap::Parser)]
 pub struct Config {
     #[clap(flatten)]
     data_dir_config: DataDirConfig,
+
+    /// 清空索引并重新索引全部微博
+    #[clap(long)]
+    rebuild: bool,
 }
 
 pub async fn command(config: Config) -> Result<(), anyhow::Error> {
-    let index_dir = config.data_dir_config.index_dir();
-    if index_dir.exists() {
-        info!("clear index_dir: {}", index_dir.display());
-        fs::remove_dir_all(index_dir)?;
+    if config.rebuild {
+        clear_index_dir(&config.data_dir_config)?;
     }
     config.data_dir_config.ensure_data_dir_exists()?;
 
     let storage = config.data_dir_config.storage()?;
-    let indexer = config.data_dir_config.weibo_indexer()?;
+    let indexer = open_indexer(&config.data_dir_config)?;
+    index_posts(&storage, &indexer)
+}
+
+pub(super) fn open_indexer(data_dir_config: &DataDirConfig) -> Result<WeiboIndexer, anyhow::Error> {
+    match data_dir_config.weibo_indexer() {
+        Ok(indexer) => Ok(indexer),
+        // 索引的 schema 有变或者索引已损坏，只能重建。其他错误（比如 IO 错误）直接返回，不能删除索引
+        Err(e) if needs_rebuild(&e) => {
+            info!("failed to open index: {}, rebuild it", e);
+            clear_index_dir(data_dir_config)?;
+            data_dir_config.ensure_data_dir_exists()?;
+            data_dir_config.weibo_indexer()
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn needs_rebuild(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<TantivyError>(),
+        Some(
+            TantivyError::SchemaError(_)
+                | TantivyError::DataCorruption(_)
+                | TantivyError::IncompatibleIndex(_)
+        )
+    )
+}
+
+// 只索引版本有变化的微博，并删除索引中已不在 storage 里的微博
+pub(super) fn index_posts(storage: &Storage, indexer: &WeiboIndexer) -> Result<(), anyhow::Error> {
     let tombstones = storage.post_tombstones().all_post_ids()?;
 
+    // 处理完 storage 中的微博之后，剩下的即是需要从索引中删除的微博
+    let mut stale_versions = indexer.indexed_post_versions()?;
+    info!("{} weibo posts already indexed", stale_versions.len());
+
     let limit = 10000;
     let mut post_id = 0;
     loop {
@@ -31,19 +70,78 @@ pub async fn command(config: Config) -> Result<(), anyhow::Error> {
             post_id = posts[posts.len() - 1].id;
         }
 
-        let filtered_posts: Vec<Post> = posts
-            .into_iter()
-            .filter(|p| !tombstones.contains(&p.id))
-            .collect();
+        let mut changed_posts: Vec<Post> = vec![];
+        for post in posts {
+            if tombstones.contains(&post.id) {
+                continue;
+            }
+            let version = post_version(&post)?;
+            if stale_versions.remove(&post.id) != Some(version) {
+                changed_posts.push(post);
+            }
+        }
 
-        if !filtered_posts.is_empty() {
-            indexer.index_weibo_posts(&filtered_posts)?;
-            info!("indexed {} weibo posts", filtered_posts.len());
+        if !changed_posts.is_empty() {
+            indexer.index_weibo_posts(&changed_posts)?;
+            info!("indexed {} weibo posts", changed_posts.len());
         }
 
         if !should_continue {
             break;
         }
     }
+
+    if !stale_versions.is_empty() {
+        let stale_post_ids: Vec<i64> = stale_versions.into_keys().collect();
+        indexer.delete_posts(&stale_post_ids)?;
+        info!("deleted {} weibo posts from index", stale_post_ids.len());
+    }
     Ok(())
 }
+
+fn clear_index_dir(data_dir_config: &DataDirConfig) -> Result<(), anyhow::Error> {
+    let index_dir = data_dir_config.index_dir();
+    if index_dir.exists() {
+        info!("clear index_dir: {}", index_dir.display());
+        fs::remove_dir_all(index_dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+    use tantivy::schema::{Schema, INDEXED};
+    use tantivy::Index;
+
+    #[test]
+    fn test_rebuild_index_with_old_schema() -> Result<(), anyhow::Error> {
+        let data_dir = temp_dir("index-old-schema");
+        let data_dir_config = DataDirConfig {
+            data_dir: data_dir.to_str().unwrap().to_string(),
+        };
+        data_dir_config.ensure_data_dir_exists()?;
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_u64_field("id", INDEXED);
+        Index::create_in_dir(data_dir_config.index_dir(), schema_builder.build())?;
+
+        let indexer = open_indexer(&data_dir_config)?;
+        assert!(indexer.schema().get_field("created_at").is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_index_on_other_errors() -> Result<(), anyhow::Error> {
+        let data_dir = temp_dir("index-not-a-dir");
+        let data_dir_config = DataDirConfig {
+            data_dir: data_dir.to_str().unwrap().to_string(),
+        };
+        // index 不是目录，打开失败，但不应该被删除
+        fs::write(data_dir_config.index_dir(), "")?;
+
+        assert!(open_indexer(&data_dir_config).is_err());
+        assert!(data_dir_config.index_dir().is_file());
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use crate::commands::DataDirConfig;
+use crate::media::MediaArchiver;
+use crate::storage::Storage;
+use log::{info, warn};
+
+#[derive(Debug, clap::Parser)]
+pub struct Config {
+    #[clap(flatten)]
+    data_dir_config: DataDirConfig,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, clap::Parser)]
+enum Command {
+    /// 下载微博中的图片和视频封面到本地。已下载的会跳过，中断后可以继续
+    Fetch(FetchConfig),
+    /// 列出下载失败的图片
+    Broken,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct FetchConfig {
+    /// 重新下载之前失败的图片
+    #[clap(long)]
+    retry_broken: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct FetchStats {
+    archived: usize,
+    skipped: usize,
+    broken: usize,
+}
+
+pub async fn command(config: Config) -> Result<(), anyhow::Error> {
+    config.data_dir_config.ensure_data_dir_exists()?;
+    let storage = config.data_dir_config.storage()?;
+    match config.command {
+        Command::Fetch(fetch_config) => {
+            let archiver = MediaArchiver::new(config.data_dir_config.media_dir())?;
+            let stats = fetch_media(&archiver, &storage, fetch_config.retry_broken).await?;
+            info!(
+                "archived {} pictures, skipped {}, {} broken",
+                stats.archived, stats.skipped, stats.broken
+            );
+            if stats.broken > 0 {
+                info!("run `weise media broken` to list broken urls");
+            }
+        }
+        Command::Broken => {
+            for record in storage.media().broken()? {
+                println!("{}\t{}", record.url, record.error.unwrap_or_default());
+            }
+        }
+    }
+    Ok(())
+}
+
+// 每张图片下载后即记录到 storage 中，中断之后再次运行，只会下载剩下的图片
+async fn fetch_media(
+    archiver: &MediaArchiver,
+    storage: &Storage,
+    retry_broken: bool,
+) -> Result<FetchStats, anyhow::Error> {
+    let urls = storage.media().referenced_urls()?;
+    info!("{} pictures referenced by posts", urls.len());
+
+    let mut stats = FetchStats::default();
+    for url in &urls {
+        if let Some(record) = storage.media().get(url)? {
+            let archived = match &record.path {
+                Some(path) => archiver.full_path(path).exists(),
+                None => false,
+            };
+            if archived || (record.error.is_some() && !retry_broken) {
+                stats.skipped += 1;
+                continue;
+            }
+        }
+
+        match archiver.archive(url).await {
+            Ok(archived) => {
+                storage
+                    .media()
+                    .set_archived(url, &archived.sha256, &archived.path)?;
+                stats.archived += 1;
+            }
+            Err(e) => {
+                warn!("failed to fetch {}: {}", url, e);
+                storage.media().set_broken(url, &e.to_string())?;
+                stats.broken += 1;
+            }
+        }
+        if (stats.archived + stats.broken) % 100 == 0 {
+            info!("fetched {} pictures", stats.archived + stats.broken);
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{load_post, temp_dir, FakeHttpServer, FakeRequest, FakeResponse};
+    use crate::weibo::post::{MediaAsset, VideoEntry};
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_fetch_media() -> Result<(), anyhow::Error> {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let server = FakeHttpServer::start(move |req: &FakeRequest| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            match req.path.as_str() {
+                "/a.jpg" | "/same-as-a.jpg" => FakeResponse::bytes("image/jpeg", b"picture a"),
+                "/cover.jpg" => FakeResponse::bytes("image/jpeg", b"cover"),
+                _ => FakeResponse::not_found(),
+            }
+        });
+
+        let storage = Storage::open(":memory:")?;
+        let mut picture_post = load_post(include_str!("../../test_data/picture.json"));
+        picture_post.media_asset = MediaAsset::Pictures(vec![
+            format!("{}/a.jpg", server.url()),
+            format!("{}/same-as-a.jpg", server.url()),
+            format!("{}/missing.jpg", server.url()),
+        ]);
+        let mut video_post = load_post(include_str!("../../test_data/video.json"));
+        video_post.media_asset = MediaAsset::Video(VideoEntry {
+            url: "https://video.weibo.com/show?fid=1034:1".to_string(),
+            duration_secs: 10,
+            cover_picture_url: format!("{}/cover.jpg", server.url()),
+        });
+        storage.posts().batch_add(&[picture_post, video_post])?;
+
+        let media_dir = temp_dir("media");
+        let archiver = MediaArchiver::new(&media_dir)?;
+        let stats = fetch_media(&archiver, &storage, false).await?;
+        assert_eq!(
+            stats,
+            FetchStats {
+                archived: 3,
+                skipped: 0,
+                broken: 1
+            }
+        );
+
+        // 内容相同的图片只保存一份
+        let a = storage
+            .media()
+            .get(&format!("{}/a.jpg", server.url()))?
+            .unwrap();
+        let same_as_a = storage
+            .media()
+            .get(&format!("{}/same-as-a.jpg", server.url()))?
+            .unwrap();
+        assert_eq!(a.path, same_as_a.path);
+        let path = a.path.unwrap();
+        assert!(path.starts_with(&a.sha256.unwrap()[..2]));
+        assert_eq!(fs::read(archiver.full_path(&path))?, b"picture a");
+
+        let broken = storage.media().broken()?;
+        assert_eq!(broken.len(), 1);
+        assert!(broken[0].url.ends_with("/missing.jpg"));
+        assert!(broken[0].error.as_ref().unwrap().contains("404"));
+
+        // 再次运行时，已下载的和失败的都跳过
+        requests.store(0, Ordering::SeqCst);
+        let stats = fetch_media(&archiver, &storage, false).await?;
+        assert_eq!(stats.skipped, 4);
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+        let stats = fetch_media(&archiver, &storage, true).await?;
+        assert_eq!(stats.broken, 1);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        fs::remove_dir_all(&media_dir)?;
+        Ok(())
+    }
+}
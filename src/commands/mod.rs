@@ -49,10 +49,19 @@ impl DataDirConfig {
     fn index_dir(&self) -> PathBuf {
         Path::new(&self.data_dir).join("index")
     }
+
+    fn media_dir(&self) -> PathBuf {
+        Path::new(&self.data_dir).join("media")
+    }
 }
 
 pub mod crawl;
+pub mod export;
+pub mod import;
 pub mod index;
+pub mod media;
+pub mod renormalize;
 pub mod search;
+pub mod serve;
 pub mod settings;
 pub mod tombstone;
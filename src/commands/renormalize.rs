@@ -0,0 +1,117 @@
+use crate::commands::index::{index_posts, open_indexer};
+use crate::commands::DataDirConfig;
+use crate::crawler::CrawledPost;
+use crate::storage::Storage;
+use log::{info, warn};
+
+#[derive(Debug, clap::Parser)]
+pub struct Config {
+    #[clap(flatten)]
+    data_dir_config: DataDirConfig,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct RenormalizeStats {
+    posts: usize,
+    failed_posts: usize,
+}
+
+// 用当前的 RawPost::normalize 重新处理保存的原始 JSON，更新 storage 中的微博，再更新索引
+pub async fn command(config: Config) -> Result<(), anyhow::Error> {
+    config.data_dir_config.ensure_data_dir_exists()?;
+    let storage = config.data_dir_config.storage()?;
+
+    let stats = renormalize_posts(&storage)?;
+    info!(
+        "renormalized {} posts, {} failed",
+        stats.posts, stats.failed_posts
+    );
+
+    let indexer = open_indexer(&config.data_dir_config)?;
+    index_posts(&storage, &indexer)
+}
+
+// 已获取的长微博全文不在原始 JSON 中，insert_post 会保留 storage 中已有的全文
+fn renormalize_posts(storage: &Storage) -> Result<RenormalizeStats, anyhow::Error> {
+    let mut stats = RenormalizeStats::default();
+    let limit = 1000;
+    let mut post_id = 0;
+    loop {
+        let raw_posts = storage.raw_posts().get_raw_posts(post_id, limit)?;
+        let should_continue = raw_posts.len() == limit;
+        if should_continue {
+            post_id = raw_posts[raw_posts.len() - 1].0;
+        }
+
+        let mut posts = vec![];
+        for (id, raw) in raw_posts {
+            match CrawledPost::from_raw(raw) {
+                Ok(CrawledPost { post, .. }) if post.is_valid() => posts.push(post),
+                Ok(_) => {
+                    warn!("invalid post after normalizing, id: {}", id);
+                    stats.failed_posts += 1;
+                }
+                Err(e) => {
+                    warn!("failed to normalize post, id: {}, error: {}", id, e);
+                    stats.failed_posts += 1;
+                }
+            }
+        }
+        storage.posts().batch_add(&posts)?;
+        stats.posts += posts.len();
+
+        if !should_continue {
+            break;
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_renormalize_posts() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        let text: Value = serde_json::from_str(include_str!("../../test_data/text.json"))?;
+        let picture: Value = serde_json::from_str(include_str!("../../test_data/picture.json"))?;
+        let text_post = CrawledPost::from_raw(text.clone())?.post;
+        let picture_post = CrawledPost::from_raw(picture.clone())?.post;
+
+        // 模拟旧版 normalize 得到的结果
+        let mut stale_text_post = text_post.clone();
+        stale_text_post.text_raw = "旧的正文".to_string();
+        stale_text_post.long_text = Some("全文".to_string());
+        let mut stale_picture_post = picture_post.clone();
+        stale_picture_post.picture_count = 0;
+        storage
+            .posts()
+            .batch_add(&[stale_text_post, stale_picture_post])?;
+        let broken = json!({"id": 1, "text_raw": "没有 created_at"});
+        storage.raw_posts().batch_add([
+            (text_post.id, &text),
+            (picture_post.id, &picture),
+            (1, &broken),
+        ])?;
+
+        let stats = renormalize_posts(&storage)?;
+        assert_eq!(
+            stats,
+            RenormalizeStats {
+                posts: 2,
+                failed_posts: 1,
+            }
+        );
+        let stored = storage.posts().get_by_id(text_post.id)?.unwrap();
+        assert_eq!(stored.text_raw, text_post.text_raw);
+        assert_eq!(stored.long_text.as_deref(), Some("全文"));
+        assert_eq!(
+            storage.posts().get_by_id(picture_post.id)?,
+            Some(picture_post)
+        );
+        assert!(storage.posts().get_by_id(1)?.is_none());
+        Ok(())
+    }
+}
@@ -1,40 +1,335 @@
 use crate::commands::DataDirConfig;
-use crate::index::{SearchedWeiboPost, WeiboIndexer, WeiboSearchParams};
+use crate::index::{
+    SearchFacets, SearchPage, SearchedWeiboPost, SortOrder, WeiboIndexer, WeiboSearchParams,
+};
+use crate::query::{parse_boost, SearchScope};
+use crate::weibo::post::{weibo_timezone, MediaType, PostSource, PostVisibility};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, TimeZone};
+use std::io::IsTerminal;
 
 #[derive(Debug, clap::Parser)]
 pub struct Config {
     #[clap(flatten)]
     data_dir_config: DataDirConfig,
 
+    #[clap(flatten)]
+    filter: FilterConfig,
+    #[clap(short, long, default_value = "10")]
+    limit: usize,
+    /// 跳过前面的结果，比如 --offset 10 显示第 11 条开始的结果
+    #[clap(long, default_value = "0")]
+    offset: usize,
+    /// 按时间排序时，从上一次搜索输出的游标处继续
+    #[clap(long)]
+    cursor: Option<String>,
+    #[clap(long, arg_enum, default_value = "relevance")]
+    sort: SortOrder,
+    /// 摘要的最大长度(字符数)，为 0 时显示全文
+    #[clap(long, default_value = "80")]
+    snippet_len: usize,
+    /// 同时输出所有匹配微博的统计: 作者、媒体类型、原创或转发、每月数量
+    #[clap(long)]
+    facets: bool,
+    /// 同时搜索已取消收藏的微博
+    #[clap(long)]
+    include_unfaved: bool,
+    /// 输出格式。json 为一个数组，jsonl 为每行一条微博
+    #[clap(long, arg_enum, default_value = "plain")]
+    format: OutputFormat,
+}
+
+// 搜索条件，export 命令也用到
+#[derive(Debug, clap::Parser)]
+pub struct FilterConfig {
+    /// 搜索词，以空格分隔，须全部匹配。可以加上范围前缀，
+    /// 比如 text:xx(正文)、rt:xx(被转发微博的正文)、author:xx、rtauthor:xx(被转发微博的作者)
     query: Option<String>,
+    /// 不带前缀的搜索词的搜索范围，默认为全部
+    #[clap(long = "scope", arg_enum)]
+    scopes: Vec<SearchScope>,
+    /// 调整各范围的权重，比如 --boost rt=0.5 使被转发微博中的匹配排名靠后
+    #[clap(long = "boost", parse(try_from_str = parse_boost))]
+    boosts: Vec<(SearchScope, f32)>,
+    /// 使用 tantivy 的完整查询语法，字段名为 text、retweeted_text、user、retweeted_user 等，
+    /// 比如 (text:北京 OR retweeted_text:上海) -user:某人
+    #[clap(long)]
+    advanced: bool,
+    /// 按媒体类型过滤，转发微博按被转发微博的媒体类型。以下几项同样如此
+    #[clap(long, arg_enum)]
+    media: Option<MediaType>,
+    /// 至少有 N 张图片，比如 --has-pictures 4+
+    #[clap(long, parse(try_from_str = parse_min_count))]
+    has_pictures: Option<u32>,
+    /// 视频时长超过该时长，比如 60s、2m、1m30s
+    #[clap(long, parse(try_from_str = parse_duration_secs))]
+    video_longer_than: Option<u32>,
+    /// 只搜索转发微博
+    #[clap(long, conflicts_with = "originals-only")]
+    retweets_only: bool,
+    /// 只搜索原创微博
     #[clap(long)]
-    media_type: Option<u8>,
+    originals_only: bool,
+    /// 按此微博及被转发微博的可见状态过滤，比如 visible 表示排除已删除等不可见的微博
+    #[clap(long, arg_enum)]
+    visibility: Option<PostVisibility>,
     #[clap(short, long)]
     user: Option<String>,
-    #[clap(short, long, default_value = "10")]
-    limit: usize,
+    /// 只搜索该日期(含)之后发布的微博，格式为 YYYY-MM-DD
+    #[clap(long)]
+    since: Option<NaiveDate>,
+    /// 只搜索该日期(含)之前发布的微博，格式为 YYYY-MM-DD
+    #[clap(long)]
+    until: Option<NaiveDate>,
+    /// 只搜索该日期(含)之后收藏的微博，格式为 YYYY-MM-DD。收藏时间未知的微博不包括在内
+    #[clap(long)]
+    faved_since: Option<NaiveDate>,
+    /// 只搜索从该来源抓取到的微博: favorites、mine、likes 或者 user:<uid>，见 weise crawl --source
+    #[clap(long)]
+    source: Option<PostSource>,
+}
+
+impl FilterConfig {
+    // 没有任何搜索条件
+    pub(super) fn is_empty(&self) -> bool {
+        self.query.is_none()
+            && self.media.is_none()
+            && self.has_pictures.is_none()
+            && self.video_longer_than.is_none()
+            && !self.retweets_only
+            && !self.originals_only
+            && self.visibility.is_none()
+            && self.user.is_none()
+            && self.since.is_none()
+            && self.until.is_none()
+            && self.faved_since.is_none()
+            && self.source.is_none()
+    }
+
+    pub(super) fn to_params(&self) -> WeiboSearchParams {
+        WeiboSearchParams {
+            media_type: self.media,
+            min_pictures: self.has_pictures,
+            video_longer_than: self.video_longer_than,
+            retweet: match (self.retweets_only, self.originals_only) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            visibility: self.visibility,
+            user: self.user.clone(),
+            query: self.query.clone(),
+            scopes: if self.scopes.is_empty() {
+                SearchScope::ALL.to_vec()
+            } else {
+                self.scopes.clone()
+            },
+            boosts: self.boosts.iter().copied().collect(),
+            advanced: self.advanced,
+            since: self.since.map(start_of_day),
+            until: self
+                .until
+                .map(|date| start_of_day(date + Duration::days(1))),
+            faved_since: self.faved_since.map(start_of_day),
+            source: self.source,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+enum OutputFormat {
+    Plain,
+    Table,
+    Json,
+    Jsonl,
 }
 
 pub async fn command(config: Config) -> Result<(), anyhow::Error> {
     config.data_dir_config.ensure_data_dir_exists()?;
 
     let params = WeiboSearchParams {
-        media_type: config.media_type,
-        user: config.user,
-        query: config.query,
+        sort: config.sort,
+        offset: config.offset,
+        limit: config.limit,
+        cursor: config.cursor,
+        snippet_len: config.snippet_len,
+        include_unfaved: config.include_unfaved,
+        ..config.filter.to_params()
     };
 
     let weibo_indexer = WeiboIndexer::with_index_dir(config.data_dir_config.index_dir())?;
-    let posts = weibo_indexer.search(&params, config.limit)?;
-    for post in posts {
-        prettify_post(&post);
+    let page = weibo_indexer.search(&params)?;
+    let posts = &page.posts;
+    let mut summary = page_summary(&page);
+    if config.facets {
+        let facets = weibo_indexer.facets(&params, 10)?;
+        summary.push_str(&format!("\n\n{}", format_facets(&facets)));
+    }
+    match config.format {
+        OutputFormat::Plain => {
+            let colored = std::io::stdout().is_terminal();
+            for post in posts {
+                prettify_post(post, colored);
+            }
+            println!("{}", summary);
+        }
+        OutputFormat::Table => {
+            print_table(posts);
+            println!("\n{}", summary);
+        }
+        // 结果之外的信息输出到 stderr，stdout 只有 JSON
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(posts)?);
+            eprintln!("{}", summary);
+        }
+        OutputFormat::Jsonl => {
+            for post in posts {
+                println!("{}", serde_json::to_string(post)?);
+            }
+            eprintln!("{}", summary);
+        }
     }
     Ok(())
 }
 
-fn prettify_post(post: &SearchedWeiboPost) {
+// 比如 showing 11–20 of 342，还有下一页时给出游标
+fn page_summary(page: &SearchPage) -> String {
+    let mut s = if page.posts.is_empty() {
+        format!("no posts on this page, {} matched", page.total)
+    } else {
+        format!(
+            "showing {}–{} of {}",
+            page.offset + 1,
+            page.offset + page.posts.len(),
+            page.total
+        )
+    };
+    if let Some(cursor) = &page.next_cursor {
+        s.push_str(&format!(", next page: --cursor {}", cursor));
+    }
+    s
+}
+
+// 4+ 或者 4
+fn parse_min_count(s: &str) -> Result<u32, anyhow::Error> {
+    s.strip_suffix('+')
+        .unwrap_or(s)
+        .parse()
+        .map_err(|_| anyhow::format_err!("invalid count {:?}, expected N or N+", s))
+}
+
+// 90、90s、2m、1m30s、1h
+fn parse_duration_secs(s: &str) -> Result<u32, anyhow::Error> {
+    let invalid = || anyhow::format_err!("invalid duration {:?}, expected e.g. 60s, 2m, 1m30s", s);
+    if let Ok(secs) = s.parse() {
+        return Ok(secs);
+    }
+
+    let mut secs = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        let n: u32 = number.parse().map_err(|_| invalid())?;
+        secs = n
+            .checked_mul(unit)
+            .and_then(|n| n.checked_add(secs))
+            .ok_or_else(invalid)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+    Ok(secs)
+}
+
+fn format_facets(facets: &SearchFacets) -> String {
+    let join = |counts: Vec<(&str, u64)>| {
+        counts
+            .iter()
+            .map(|(name, count)| format!("{} {}", name, count))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let authors = facets
+        .authors
+        .iter()
+        .map(|(author, count)| (author.as_str(), *count))
+        .collect();
+    let media_types = facets
+        .media_types
+        .iter()
+        .map(|(media_type, count)| (media_type_name(*media_type), *count))
+        .collect();
+    let months = facets
+        .months
+        .iter()
+        .map(|(month, count)| (month.as_str(), *count))
+        .collect();
+    format!(
+        "authors: {}\nmedia:   {}\nkind:    {}\nmonths:  {}",
+        join(authors),
+        join(media_types),
+        join(vec![
+            ("original", facets.originals),
+            ("retweet", facets.retweets)
+        ]),
+        join(months),
+    )
+}
+
+fn media_type_name(media_type: MediaType) -> &'static str {
+    match media_type {
+        MediaType::Text => "text",
+        MediaType::Picture => "picture",
+        MediaType::Video => "video",
+    }
+}
+
+pub(super) fn start_of_day(date: NaiveDate) -> DateTime<FixedOffset> {
+    weibo_timezone()
+        .from_local_datetime(&date.and_hms(0, 0, 0))
+        .unwrap()
+}
+
+fn prettify_post(post: &SearchedWeiboPost, colored: bool) {
+    let mut s = format!("{} {}", post.url, post.created_at.format("%Y-%m-%d %H:%M"));
+    if post.visibility != PostVisibility::Visible {
+        s.push_str(&format!(" [{}]", post.visibility.label()));
+    }
+    if post.unfaved {
+        s.push_str(" [已取消收藏]");
+    }
+
+    // 有摘要时只显示摘要，匹配部分在终端中高亮
+    if let Some(snippet) = &post.snippet {
+        let fragment = if colored {
+            snippet.to_ansi()
+        } else {
+            snippet.fragment.clone()
+        };
+        let fragment = fragment.replace("\n", " ");
+        match (snippet.field.as_str(), &post.retweeted_user) {
+            ("retweeted_text", Some(retweeted_user)) => s.push_str(&format!(
+                "\n@{} //@{}: ...{}...",
+                post.user, retweeted_user, fragment
+            )),
+            _ => s.push_str(&format!("\n@{}: ...{}...", post.user, fragment)),
+        }
+        println!("{}\n", s);
+        return;
+    }
+
     let text = post.text.replace("\n", " ");
-    let mut s = format!("{}\n@{}: {}", post.url, post.user, text);
+    s.push_str(&format!("\n@{}: {}", post.user, text));
     if let Some(retweeted_user) = &post.retweeted_user {
         let tmp = format!("  @{}: ", retweeted_user);
         s.push_str(&tmp);
@@ -45,3 +340,152 @@ fn prettify_post(post: &SearchedWeiboPost) {
     }
     println!("{}\n", s);
 }
+
+// 每条微博一行，文本只显示开头的部分
+fn print_table(posts: &[SearchedWeiboPost]) {
+    let user_width = posts
+        .iter()
+        .map(|post| display_width(&post.user))
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    println!(
+        "{:<16}  {:<16}  {}  {:<7}  TEXT",
+        "ID",
+        "DATE",
+        pad("USER", user_width),
+        "MEDIA"
+    );
+    for post in posts {
+        let media = match post.media_type {
+            MediaType::Text => "-",
+            MediaType::Picture => "picture",
+            MediaType::Video => "video",
+        };
+        let mut text = match &post.snippet {
+            Some(snippet) => snippet.fragment.replace("\n", " "),
+            None => post.text.replace("\n", " "),
+        };
+        if let Some(retweeted_user) = &post.retweeted_user {
+            text.push_str(&format!(" //@{}", retweeted_user));
+        }
+        println!(
+            "{:<16}  {:<16}  {}  {:<7}  {}",
+            post.id,
+            post.created_at.format("%Y-%m-%d %H:%M"),
+            pad(&post.user, user_width),
+            media,
+            truncate(&text, 60)
+        );
+    }
+}
+
+// 终端中的显示宽度，中文等全角字符占两列
+fn display_width(s: &str) -> usize {
+    s.chars()
+        .map(|c| if (c as u32) < 0x1100 { 1 } else { 2 })
+        .sum()
+}
+
+fn pad(s: &str, width: usize) -> String {
+    let padding = width.saturating_sub(display_width(s));
+    format!("{}{}", s, " ".repeat(padding))
+}
+
+fn truncate(s: &str, width: usize) -> String {
+    if display_width(s) <= width {
+        return s.to_string();
+    }
+    let mut truncated = String::new();
+    let mut truncated_width = 0;
+    for c in s.chars() {
+        let c_width = display_width(c.encode_utf8(&mut [0; 4]));
+        if truncated_width + c_width > width - 3 {
+            break;
+        }
+        truncated.push(c);
+        truncated_width += c_width;
+    }
+    truncated.push_str("...");
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("hello", 10), "hello");
+        assert_eq!(truncate("今年我一定会开一家新公司", 10), "今年我...");
+        assert_eq!(pad("微博", 6), "微博  ");
+    }
+
+    #[test]
+    fn test_parse_filters() {
+        assert_eq!(parse_min_count("4+").unwrap(), 4);
+        assert_eq!(parse_min_count("4").unwrap(), 4);
+        assert!(parse_min_count("+").is_err());
+
+        assert_eq!(parse_duration_secs("90").unwrap(), 90);
+        assert_eq!(parse_duration_secs("60s").unwrap(), 60);
+        assert_eq!(parse_duration_secs("1m30s").unwrap(), 90);
+        assert_eq!(parse_duration_secs("1h").unwrap(), 3600);
+        assert!(parse_duration_secs("1m30").is_err());
+        assert!(parse_duration_secs("m").is_err());
+        assert!(parse_duration_secs("1d").is_err());
+    }
+
+    #[test]
+    fn test_format_facets() {
+        let facets = SearchFacets {
+            authors: vec![("郭宇".to_string(), 2), ("木遥".to_string(), 1)],
+            media_types: vec![(MediaType::Text, 2), (MediaType::Picture, 1)],
+            originals: 1,
+            retweets: 2,
+            months: vec![("2022-01".to_string(), 3)],
+        };
+        assert_eq!(
+            format_facets(&facets),
+            "authors: 郭宇 2, 木遥 1\n\
+             media:   text 2, picture 1\n\
+             kind:    original 1, retweet 2\n\
+             months:  2022-01 3"
+        );
+    }
+
+    #[test]
+    fn test_page_summary() {
+        let mut page = SearchPage {
+            total: 342,
+            offset: 340,
+            posts: vec![],
+            next_cursor: None,
+        };
+        assert_eq!(page_summary(&page), "no posts on this page, 342 matched");
+
+        let post = SearchedWeiboPost {
+            id: 1,
+            created_at: start_of_day(NaiveDate::from_ymd(2022, 1, 1)),
+            visibility: PostVisibility::Visible,
+            faved_at: None,
+            unfaved: false,
+            url: String::new(),
+            user: String::new(),
+            text: String::new(),
+            media_type: MediaType::Text,
+            media_urls: vec![],
+            retweeted_user: None,
+            retweeted_text: None,
+            score: None,
+            snippet: None,
+        };
+        page.offset = 10;
+        page.posts = vec![post; 10];
+        page.next_cursor = Some("1640966400_1".to_string());
+        assert_eq!(
+            page_summary(&page),
+            "showing 11–20 of 342, next page: --cursor 1640966400_1"
+        );
+    }
+}
@@ -0,0 +1,574 @@
+use crate::commands::search::start_of_day;
+use crate::commands::DataDirConfig;
+use crate::index::{PostSnippet, QuerySyntaxError, SortOrder, WeiboIndexer, WeiboSearchParams};
+use crate::render::{escape_html, media_urls, render_post, PAGE_STYLE};
+use crate::storage::Storage;
+use crate::weibo::post::{MediaType, Post, PostSource, PostVisibility};
+use axum::extract::{Path as UrlPath, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{Duration, NaiveDate};
+use clap::ArgEnum;
+use log::{info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock, Mutex};
+
+// 本地的搜索页面，以及 JSON 接口 /api/search。两者的参数相同，见 SearchQuery
+
+#[derive(Debug, clap::Parser)]
+pub struct Config {
+    #[clap(flatten)]
+    data_dir_config: DataDirConfig,
+
+    /// 监听的地址
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    addr: SocketAddr,
+}
+
+pub async fn command(config: Config) -> Result<(), anyhow::Error> {
+    config.data_dir_config.ensure_data_dir_exists()?;
+    let state = AppState {
+        indexer: config.data_dir_config.weibo_indexer()?,
+        storage: Mutex::new(config.data_dir_config.storage()?),
+        media_dir: config.data_dir_config.media_dir(),
+    };
+
+    info!("serving on http://{}", config.addr);
+    axum::Server::bind(&config.addr)
+        .serve(router(state).into_make_service())
+        .await?;
+    Ok(())
+}
+
+struct AppState {
+    indexer: WeiboIndexer,
+    storage: Mutex<Storage>,
+    media_dir: PathBuf,
+}
+
+fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(search_page))
+        .route("/api/search", get(search_api))
+        .route("/media/:dir/:file", get(media_file))
+        .with_state(Arc::new(state))
+}
+
+// 表单中未填写的字段，会以空字符串提交
+#[derive(Debug, Default, Deserialize)]
+struct SearchQuery {
+    q: Option<String>,
+    // 复选框，选中时为 on
+    advanced: Option<String>,
+    include_unfaved: Option<String>,
+    user: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    sort: Option<String>,
+    visibility: Option<String>,
+    // text、picture 或者 video
+    media: Option<String>,
+    // favorites、mine、likes 或者 user:<uid>
+    source: Option<String>,
+    limit: Option<String>,
+    offset: Option<String>,
+    cursor: Option<String>,
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+impl SearchQuery {
+    fn to_params(&self) -> Result<WeiboSearchParams, AppError> {
+        let parse_date = |name: &str, value: &Option<String>| {
+            non_empty(value)
+                .map(|v| NaiveDate::parse_from_str(v, "%Y-%m-%d"))
+                .transpose()
+                .map_err(|_| AppError::bad_request(format!("invalid {}, expect YYYY-MM-DD", name)))
+        };
+        let since = parse_date("since", &self.since)?;
+        let until = parse_date("until", &self.until)?;
+
+        // 没有搜索词时，默认按时间倒序
+        let sort = match non_empty(&self.sort) {
+            Some(sort) => SortOrder::from_str(sort, true).map_err(AppError::bad_request)?,
+            None if non_empty(&self.q).is_some() => SortOrder::Relevance,
+            None => SortOrder::Newest,
+        };
+        let visibility = non_empty(&self.visibility)
+            .map(|v| PostVisibility::from_str(v, true))
+            .transpose()
+            .map_err(AppError::bad_request)?;
+        let media_type = non_empty(&self.media)
+            .map(|v| MediaType::from_str(v, true))
+            .transpose()
+            .map_err(AppError::bad_request)?;
+        let source = non_empty(&self.source)
+            .map(|v| v.parse::<PostSource>())
+            .transpose()
+            .map_err(|e| AppError::bad_request(e.to_string()))?;
+        let limit = non_empty(&self.limit)
+            .map(|v| v.parse::<usize>())
+            .transpose()
+            .map_err(|_| AppError::bad_request("invalid limit".to_string()))?
+            .unwrap_or(20)
+            .min(200);
+        let offset = non_empty(&self.offset)
+            .map(|v| v.parse::<usize>())
+            .transpose()
+            .map_err(|_| AppError::bad_request("invalid offset".to_string()))?
+            .unwrap_or(0);
+
+        let params = WeiboSearchParams {
+            media_type,
+            visibility,
+            user: non_empty(&self.user).map(str::to_string),
+            query: non_empty(&self.q).map(str::to_string),
+            advanced: non_empty(&self.advanced).is_some(),
+            include_unfaved: non_empty(&self.include_unfaved).is_some(),
+            source,
+            since: since.map(start_of_day),
+            until: until.map(|date| start_of_day(date + Duration::days(1))),
+            sort,
+            offset,
+            limit,
+            cursor: non_empty(&self.cursor).map(str::to_string),
+            snippet_len: 80,
+            ..Default::default()
+        };
+        Ok(params)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SearchResponse {
+    // 匹配的微博总数，以及本页第一条微博的位置
+    total: usize,
+    offset: usize,
+    // 下一页的 offset 参数。按索引返回的条数计算，不受 storage 中缺失的微博影响
+    next_offset: usize,
+    // 按时间排序时，下一页的 cursor 参数
+    next_cursor: Option<String>,
+    posts: Vec<Post>,
+    // 已存档到本地的图片: 原 url -> 本地 url
+    archived_media: HashMap<String, String>,
+    // 微博 id -> 摘要，只在有搜索词时返回
+    snippets: HashMap<i64, PostSnippet>,
+}
+
+fn search(state: &AppState, query: &SearchQuery) -> Result<SearchResponse, AppError> {
+    let params = query.to_params()?;
+    let page = state.indexer.search(&params)?;
+
+    let storage = state.storage.lock().unwrap();
+    let mut posts = vec![];
+    let mut archived_media = HashMap::new();
+    let mut snippets = HashMap::new();
+    let next_offset = page.offset + page.posts.len();
+    for hit in page.posts {
+        // 索引尚未更新时，可能有已不在 storage 中的微博
+        let post = match storage.posts().get_by_id(hit.id)? {
+            Some(post) => post,
+            None => {
+                warn!("post {} is in the index but not in storage", hit.id);
+                continue;
+            }
+        };
+        for url in media_urls(&post) {
+            if let Some(path) = storage.media().archived_path(&url)? {
+                archived_media.insert(url, format!("/media/{}", path));
+            }
+        }
+        if let Some(snippet) = hit.snippet {
+            snippets.insert(post.id, snippet);
+        }
+        posts.push(post);
+    }
+    Ok(SearchResponse {
+        total: page.total,
+        offset: page.offset,
+        next_offset,
+        next_cursor: page.next_cursor,
+        posts,
+        archived_media,
+        snippets,
+    })
+}
+
+// tantivy 和 sqlite 的调用都是同步的，放到 spawn_blocking 中执行，以免阻塞异步运行时
+async fn search_api(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchResponse>, AppError> {
+    let response = tokio::task::spawn_blocking(move || search(&state, &query))
+        .await
+        .map_err(|e| AppError::from(anyhow::Error::from(e)))??;
+    Ok(Json(response))
+}
+
+async fn search_page(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Html<String>, AppError> {
+    let html = tokio::task::spawn_blocking(move || {
+        let result = search(&state, &query);
+        render_page(&query, result)
+    })
+    .await
+    .map_err(|e| AppError::from(anyhow::Error::from(e)))?;
+    Ok(Html(html))
+}
+
+// 只允许访问 media fetch 保存的文件，形如 3f/3fa2...9c.jpg
+static MEDIA_FILE_NAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[0-9a-f]{64}\.([0-9a-z]{1,5})$").unwrap());
+
+async fn media_file(
+    State(state): State<Arc<AppState>>,
+    UrlPath((dir, file)): UrlPath<(String, String)>,
+) -> Response {
+    let ext = match MEDIA_FILE_NAME.captures(&file) {
+        Some(caps) if file.starts_with(&dir) && dir.len() == 2 => caps[1].to_string(),
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let content_type = match ext.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    };
+    match fs::read(state.media_dir.join(&dir).join(&file)) {
+        Ok(bytes) => ([(header::CONTENT_TYPE, content_type)], bytes).into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+struct AppError {
+    status: StatusCode,
+    message: String,
+}
+
+impl AppError {
+    fn bad_request(message: String) -> AppError {
+        AppError {
+            status: StatusCode::BAD_REQUEST,
+            message,
+        }
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(e: anyhow::Error) -> AppError {
+        if e.is::<QuerySyntaxError>() {
+            return AppError::bad_request(e.to_string());
+        }
+        AppError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: e.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let body = Json(serde_json::json!({ "error": self.message }));
+        (self.status, body).into_response()
+    }
+}
+
+fn render_page(query: &SearchQuery, result: Result<SearchResponse, AppError>) -> String {
+    let field = |value: &Option<String>| escape_html(non_empty(value).unwrap_or_default());
+    let option = |name: &str, label: &str, value: &Option<String>| {
+        let selected = if non_empty(value) == Some(name) {
+            " selected"
+        } else {
+            ""
+        };
+        format!(r#"<option value="{}"{}>{}</option>"#, name, selected, label)
+    };
+
+    let mut html = String::new();
+    write!(
+        html,
+        r#"<!DOCTYPE html>
+<html lang="zh-CN">
+<head><meta charset="utf-8"><title>weise</title><style>{}</style></head>
+<body>
+<form method="get" action="/">
+<input name="q" value="{}" placeholder="搜索收藏的微博">
+<label><input name="advanced" type="checkbox"{}>高级语法</label>
+<input name="user" value="{}" placeholder="作者">
+<input name="since" type="date" value="{}">
+<input name="until" type="date" value="{}">
+<select name="sort">{}{}{}{}</select>
+<select name="visibility">{}{}{}{}{}</select>
+<label><input name="include_unfaved" type="checkbox"{}>包括已取消收藏</label>
+<button type="submit">搜索</button>
+</form>
+"#,
+        PAGE_STYLE,
+        field(&query.q),
+        if non_empty(&query.advanced).is_some() {
+            " checked"
+        } else {
+            ""
+        },
+        field(&query.user),
+        field(&query.since),
+        field(&query.until),
+        option("", "默认排序", &query.sort),
+        option("relevance", "相关度", &query.sort),
+        option("newest", "最新", &query.sort),
+        option("oldest", "最早", &query.sort),
+        option("", "全部", &query.visibility),
+        option("visible", "可见", &query.visibility),
+        option("deleted", "已删除", &query.visibility),
+        option("restricted", "作者设置不可见", &query.visibility),
+        option("censored", "无法查看", &query.visibility),
+        if non_empty(&query.include_unfaved).is_some() {
+            " checked"
+        } else {
+            ""
+        },
+    )
+    .unwrap();
+
+    match result {
+        Ok(result) => {
+            if result.posts.is_empty() {
+                html.push_str("<p>没有找到微博</p>\n");
+            }
+            for post in &result.posts {
+                html.push_str(r#"<article class="post">"#);
+                if let Some(snippet) = result.snippets.get(&post.id) {
+                    write!(html, r#"<p class="snippet">…{}…</p>"#, snippet.html).unwrap();
+                }
+                render_post(&mut html, post, &result.archived_media);
+                html.push_str("</article>\n");
+            }
+            render_pager(&mut html, query, &result);
+        }
+        Err(e) => {
+            write!(html, r#"<p class="error">{}</p>"#, escape_html(&e.message)).unwrap();
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+// 当前位置，以及保留搜索条件的下一页按钮
+fn render_pager(html: &mut String, query: &SearchQuery, result: &SearchResponse) {
+    if result.next_offset == result.offset {
+        return;
+    }
+    write!(
+        html,
+        r#"<nav class="pager">第 {}–{} 条，共 {} 条"#,
+        result.offset + 1,
+        result.next_offset,
+        result.total
+    )
+    .unwrap();
+
+    if result.next_offset < result.total {
+        html.push_str(r#"<form method="get" action="/">"#);
+        let fields = [
+            ("q", &query.q),
+            ("advanced", &query.advanced),
+            ("user", &query.user),
+            ("since", &query.since),
+            ("until", &query.until),
+            ("sort", &query.sort),
+            ("visibility", &query.visibility),
+            ("include_unfaved", &query.include_unfaved),
+            ("media", &query.media),
+            ("source", &query.source),
+            ("limit", &query.limit),
+        ];
+        let mut hidden = |name: &str, value: &str| {
+            write!(
+                html,
+                r#"<input type="hidden" name="{}" value="{}">"#,
+                name,
+                escape_html(value)
+            )
+            .unwrap();
+        };
+        for (name, value) in fields {
+            if let Some(value) = non_empty(value) {
+                hidden(name, value);
+            }
+        }
+        match &result.next_cursor {
+            Some(cursor) => hidden("cursor", cursor),
+            None => hidden("offset", &result.next_offset.to_string()),
+        }
+        html.push_str(r#"<button type="submit">下一页</button></form>"#);
+    }
+    html.push_str("</nav>\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::load_post;
+    use serde_json::Value;
+
+    async fn start_server() -> Result<String, anyhow::Error> {
+        let posts = vec![
+            load_post(include_str!("../../test_data/text.json")),
+            load_post(include_str!("../../test_data/picture_retweet.json")),
+        ];
+        let storage = Storage::open(":memory:")?;
+        storage.posts().batch_add(&posts)?;
+        let indexer = WeiboIndexer::in_ram();
+        indexer.index_weibo_posts(&posts)?;
+
+        let state = AppState {
+            indexer,
+            storage: Mutex::new(storage),
+            media_dir: std::env::temp_dir().join("weise-serve-media"),
+        };
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse()?).serve(router(state).into_make_service());
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        Ok(url)
+    }
+
+    #[tokio::test]
+    async fn test_search_api() -> Result<(), anyhow::Error> {
+        let url = start_server().await?;
+
+        let resp = reqwest::get(format!("{}/api/search?q=公司", url))
+            .await?
+            .text()
+            .await?;
+        let resp: Value = serde_json::from_str(&resp)?;
+        let posts = resp["posts"].as_array().unwrap();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0]["id"], 4723695598438753i64);
+        let snippet = &resp["snippets"]["4723695598438753"];
+        assert!(snippet["html"]
+            .as_str()
+            .unwrap()
+            .contains("<mark>公司</mark>"));
+
+        // 没有搜索词时，按时间倒序返回全部
+        let resp = reqwest::get(format!("{}/api/search?q=&since=", url))
+            .await?
+            .text()
+            .await?;
+        let resp: Value = serde_json::from_str(&resp)?;
+        assert_eq!(resp["posts"].as_array().unwrap().len(), 2);
+        assert_eq!(resp["total"], 2);
+
+        // 按时间排序时，用 cursor 翻页
+        let resp = reqwest::get(format!("{}/api/search?limit=1", url))
+            .await?
+            .text()
+            .await?;
+        let resp: Value = serde_json::from_str(&resp)?;
+        assert_eq!(resp["posts"][0]["id"], 4723695598438753i64);
+        let cursor = resp["next_cursor"].as_str().unwrap();
+        let resp = reqwest::get(format!("{}/api/search?limit=1&cursor={}", url, cursor))
+            .await?
+            .text()
+            .await?;
+        let resp: Value = serde_json::from_str(&resp)?;
+        assert_eq!(resp["offset"], 1);
+        assert_ne!(resp["posts"][0]["id"], 4723695598438753i64);
+        assert!(resp["next_cursor"].is_null());
+
+        let resp = reqwest::get(format!("{}/api/search?since=yesterday", url)).await?;
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        // 高级语法的语法错误
+        let resp = reqwest::get(format!("{}/api/search?q=text:(&advanced=on", url)).await?;
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_page() -> Result<(), anyhow::Error> {
+        let url = start_server().await?;
+
+        let html = reqwest::get(format!("{}/?sort=newest", url))
+            .await?
+            .text()
+            .await?;
+        assert!(html.contains("https://weibo.com/1773116334/L9WqHzpiV"));
+        assert!(html.contains(r#"<blockquote class="retweet">"#));
+        assert!(html.contains(r#"referrerpolicy="no-referrer""#));
+        assert!(html.contains(r#"<option value="newest" selected>"#));
+
+        let html = reqwest::get(format!("{}/?sort=newest&limit=1", url))
+            .await?
+            .text()
+            .await?;
+        assert!(html.contains("第 1–1 条，共 2 条"));
+        assert!(html.contains(r#"<input type="hidden" name="cursor""#));
+
+        let resp = reqwest::get(format!("{}/media/..%2F/passwd", url)).await?;
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_posts_missing_from_storage() -> Result<(), anyhow::Error> {
+        let text = load_post(include_str!("../../test_data/text.json"));
+        let picture = load_post(include_str!("../../test_data/picture_retweet.json"));
+        let video = load_post(include_str!("../../test_data/video.json"));
+        let storage = Storage::open(":memory:")?;
+        storage.posts().batch_add(&[text.clone(), video.clone()])?;
+        let indexer = WeiboIndexer::in_ram();
+        indexer.index_weibo_posts(&[text.clone(), picture, video.clone()])?;
+        let state = AppState {
+            indexer,
+            storage: Mutex::new(storage),
+            media_dir: std::env::temp_dir().join("weise-serve-media"),
+        };
+        let search_page = |offset: usize| {
+            let query = SearchQuery {
+                sort: Some("oldest".to_string()),
+                limit: Some("2".to_string()),
+                offset: Some(offset.to_string()),
+                ..Default::default()
+            };
+            match search(&state, &query) {
+                Ok(response) => response,
+                Err(e) => panic!("search failed: {}", e.message),
+            }
+        };
+
+        // 缺失的微博不显示，但翻页仍按索引中的位置计算
+        let first = search_page(0);
+        assert_eq!(first.total, 3);
+        assert_eq!(first.next_offset, 2);
+        let second = search_page(first.next_offset);
+        assert_eq!(second.offset, 2);
+        assert_eq!(second.next_offset, 3);
+
+        let mut ids: Vec<i64> = first
+            .posts
+            .iter()
+            .chain(second.posts.iter())
+            .map(|p| p.id)
+            .collect();
+        ids.sort_unstable();
+        let mut expected = vec![text.id, video.id];
+        expected.sort_unstable();
+        assert_eq!(ids, expected);
+        Ok(())
+    }
+}
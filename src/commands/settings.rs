@@ -1,4 +1,5 @@
 use crate::commands::DataDirConfig;
+use crate::crawler::SessionCookie;
 use crate::storage::Storage;
 use log::error;
 
@@ -14,6 +15,7 @@ pub struct Config {
 #[derive(Debug, clap::Parser)]
 enum Command {
     Set(SetConfig),
+    Unset(UnsetConfig),
     Show,
 }
 
@@ -22,11 +24,17 @@ pub struct SetConfig {
     items: Vec<String>,
 }
 
+#[derive(Debug, clap::Parser)]
+pub struct UnsetConfig {
+    names: Vec<String>,
+}
+
 pub async fn command(config: Config) -> Result<(), anyhow::Error> {
     config.data_dir_config.ensure_data_dir_exists()?;
     let storage = config.data_dir_config.storage()?;
     match config.command {
         Command::Set(set_config) => settings_set(storage, set_config)?,
+        Command::Unset(unset_config) => settings_unset(storage, unset_config)?,
         Command::Show => settings_show(storage)?,
     }
     Ok(())
@@ -34,21 +42,48 @@ pub async fn command(config: Config) -> Result<(), anyhow::Error> {
 
 fn settings_set(storage: Storage, config: SetConfig) -> Result<(), anyhow::Error> {
     for item in &config.items {
-        let kv: Vec<&str> = item.split('=').collect();
-        if kv.len() != 2 {
-            error!("settings should be as follows: <name>=<value>");
-            continue;
-        }
+        let kv = match item.split_once('=') {
+            Some(kv) => kv,
+            None => {
+                error!("settings should be as follows: <name>=<value>");
+                continue;
+            }
+        };
 
-        let name = kv[0].trim();
+        let name = kv.0.trim();
         match name {
             "max_page" => {
-                let value = kv[1].trim();
+                let value = kv.1.trim();
                 let value: u32 = value.parse().map_err(|_e| {
                     anyhow::format_err!("max_page should be an integer, instead of {}", value)
                 })?;
                 storage.settings().set_max_page(value)?;
             }
+            "uid" => {
+                let value = kv.1.trim();
+                let value: i64 = value.parse().map_err(|_e| {
+                    anyhow::format_err!("uid should be an integer, instead of {}", value)
+                })?;
+                storage.settings().set_uid(value)?;
+            }
+            "session_cookies" => {
+                // 值为浏览器中复制出来的 Cookie 请求头
+                let cookies = SessionCookie::parse_header(kv.1)?;
+                storage.settings().set_session_cookies(&cookies)?;
+            }
+            _ => {
+                return Err(anyhow::format_err!("settings not supported: {}", name));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn settings_unset(storage: Storage, config: UnsetConfig) -> Result<(), anyhow::Error> {
+    for name in &config.names {
+        let name = name.trim();
+        match name {
+            "max_page" | "uid" | "session_cookies" => storage.settings().delete(name)?,
             _ => {
                 return Err(anyhow::format_err!("settings not supported: {}", name));
             }
@@ -62,5 +97,13 @@ fn settings_show(storage: Storage) -> Result<(), anyhow::Error> {
         Some(max_page) => println!("max_page = {}", max_page),
         None => println!("max_page = <unset>"),
     }
+    match storage.settings().get_uid()? {
+        Some(uid) => println!("uid = {}", uid),
+        None => println!("uid = <unset>"),
+    }
+    match storage.settings().get_session_cookies()? {
+        Some(cookies) => println!("session_cookies = <{} cookies>", cookies.len()),
+        None => println!("session_cookies = <unset>"),
+    }
     Ok(())
 }
@@ -0,0 +1,206 @@
+use crate::crawler::{
+    long_text_url, CrawledPost, LoginConfig, LongTextResponse, PostList, PostListResponse,
+    SessionCookie, WeiboCrawler, BROWSER_USER_AGENT, WEIBO_URL,
+};
+use async_trait::async_trait;
+use log::info;
+use reqwest::header::{HeaderMap, HeaderValue, COOKIE, REFERER, USER_AGENT};
+
+// 直接以 HTTP 请求微博的 ajax 接口，不需要 Chrome/chromedriver。
+// 无法交互式登录，只能使用已保存的 cookie。
+pub struct HttpWeiboClient {
+    client: reqwest::Client,
+    base_url: String,
+    cookies: Vec<SessionCookie>,
+}
+
+impl HttpWeiboClient {
+    pub async fn login(login_config: &LoginConfig) -> Result<HttpWeiboClient, anyhow::Error> {
+        HttpWeiboClient::connect(WEIBO_URL, &login_config.saved_cookies).await
+    }
+
+    pub async fn connect(
+        base_url: &str,
+        cookies: &[SessionCookie],
+    ) -> Result<HttpWeiboClient, anyhow::Error> {
+        let cookies: Vec<SessionCookie> = cookies
+            .iter()
+            .filter(|cookie| !cookie.is_expired())
+            .cloned()
+            .collect();
+        if cookies.is_empty() {
+            return Err(anyhow::format_err!(
+                "no saved session cookies, login with `weise crawl --backend webdriver` \
+                 or `weise settings set session_cookies=<cookie header>` first"
+            ));
+        }
+
+        let cookie_header = cookies
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect::<Vec<String>>()
+            .join("; ");
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(&cookie_header)?);
+        headers.insert(USER_AGENT, HeaderValue::from_static(BROWSER_USER_AGENT));
+        headers.insert(REFERER, HeaderValue::from_str(&format!("{}/", base_url))?);
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()?;
+
+        let client = HttpWeiboClient {
+            client,
+            base_url: base_url.to_string(),
+            cookies,
+        };
+        if !client.get_post_list(PostList::Favorites, 1).await?.is_ok() {
+            return Err(anyhow::format_err!(
+                "saved session cookies are no longer valid, please login again"
+            ));
+        }
+        info!("logged in with saved session cookies");
+        Ok(client)
+    }
+
+    async fn get_post_list(
+        &self,
+        list: PostList,
+        page_id: u32,
+    ) -> Result<PostListResponse, anyhow::Error> {
+        let content = self.get(&list.page_url(&self.base_url, page_id)).await?;
+        let res: PostListResponse = serde_json::from_str(&content)?;
+        Ok(res)
+    }
+
+    async fn get(&self, url: &str) -> Result<String, anyhow::Error> {
+        let content = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(content)
+    }
+}
+
+#[async_trait]
+impl WeiboCrawler for HttpWeiboClient {
+    async fn get_posts_by_page(
+        &self,
+        list: PostList,
+        page_id: u32,
+    ) -> Result<Vec<CrawledPost>, anyhow::Error> {
+        let res = self.get_post_list(list, page_id).await?;
+        if !res.is_ok() {
+            return Err(anyhow::format_err!(
+                "failed to get page {} of {:?}",
+                page_id,
+                list
+            ));
+        }
+        res.into_crawled_posts()
+    }
+
+    async fn get_long_text(&self, mblogid: &str) -> Result<Option<String>, anyhow::Error> {
+        let content = self.get(&long_text_url(&self.base_url, mblogid)).await?;
+        let res: LongTextResponse = serde_json::from_str(&content)?;
+        res.into_long_text()
+    }
+
+    async fn session_cookies(&self) -> Result<Vec<SessionCookie>, anyhow::Error> {
+        Ok(self.cookies.clone())
+    }
+
+    async fn close(self: Box<Self>) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawler::fill_long_text;
+    use crate::test_util::{FakeHttpServer, FakeRequest, FakeResponse};
+    use crate::weibo::raw::RawPost;
+    use serde_json::{json, Value};
+    use std::collections::HashSet;
+
+    // 模拟收藏接口：cookie 中带有 SUB 时视为已登录，第 1 页为 test_data 中的微博，之后为空
+    fn fake_weibo() -> FakeHttpServer {
+        FakeHttpServer::start(|req: &FakeRequest| {
+            let logged_in = req
+                .headers
+                .get("cookie")
+                .is_some_and(|cookie| cookie.contains("SUB="));
+            if !logged_in {
+                return FakeResponse::json(
+                    json!({"ok": -100, "url": "https://weibo.com/login.php"}),
+                );
+            }
+            if req.path == "/ajax/statuses/longtext?id=L9Qq7qGfn" {
+                return FakeResponse::json(
+                    serde_json::from_str(include_str!("../../test_data/long_text.json")).unwrap(),
+                );
+            }
+            if req.path == "/ajax/favorites/all_fav?page=1" {
+                let data: Vec<Value> = vec![
+                    serde_json::from_str(include_str!("../../test_data/text.json")).unwrap(),
+                    serde_json::from_str(include_str!("../../test_data/video_retweet.json"))
+                        .unwrap(),
+                ];
+                return FakeResponse::json(json!({"ok": 1, "data": data}));
+            }
+            FakeResponse::json(json!({"ok": 1, "data": []}))
+        })
+    }
+
+    #[tokio::test]
+    async fn test_get_posts_by_page() -> Result<(), anyhow::Error> {
+        let server = fake_weibo();
+        let cookies = SessionCookie::parse_header("SUB=abc; SUBP=def")?;
+        let client = HttpWeiboClient::connect(&server.url(), &cookies).await?;
+
+        let posts = client.get_posts_by_page(PostList::Favorites, 1).await?;
+        assert_eq!(posts.len(), 2);
+        assert!(!posts[0].post.is_retweet());
+        assert_eq!(posts[0].raw["id"], posts[0].post.id);
+        assert!(posts[1].post.is_retweet());
+        assert!(client
+            .get_posts_by_page(PostList::Favorites, 2)
+            .await?
+            .is_empty());
+        assert_eq!(client.session_cookies().await?, cookies);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fill_long_text() -> Result<(), anyhow::Error> {
+        let server = fake_weibo();
+        let cookies = SessionCookie::parse_header("SUB=abc")?;
+        let client = HttpWeiboClient::connect(&server.url(), &cookies).await?;
+
+        let raw: RawPost = serde_json::from_str(include_str!("../../test_data/text_retweet.json"))?;
+        let mut post = raw.normalize();
+        assert!(post.needs_long_text());
+        let mut missing_ids = vec![];
+        assert!(fill_long_text(&client, &mut post, &HashSet::new(), &mut missing_ids).await);
+        assert!(missing_ids.is_empty());
+        assert!(!post.needs_long_text());
+        let retweeted_post = post.retweeted_post.unwrap();
+        assert!(retweeted_post.full_text().ends_with("值得一读。"));
+        assert!(retweeted_post.text_raw.len() < retweeted_post.full_text().len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_invalid_cookies() -> Result<(), anyhow::Error> {
+        let server = fake_weibo();
+        let cookies = SessionCookie::parse_header("OTHER=abc")?;
+        let res = HttpWeiboClient::connect(&server.url(), &cookies).await;
+        assert!(res.is_err());
+        assert!(HttpWeiboClient::connect(&server.url(), &[]).await.is_err());
+        Ok(())
+    }
+}
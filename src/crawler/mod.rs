@@ -0,0 +1,288 @@
+use crate::weibo::post::Post;
+use crate::weibo::raw::{RawLongText, RawPost};
+use async_trait::async_trait;
+use chrono::Utc;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::time::Duration;
+
+pub mod http;
+pub mod webdriver;
+
+pub use http::HttpWeiboClient;
+pub use webdriver::WeiboClient;
+
+pub const WEIBO_URL: &str = "https://weibo.com";
+pub(crate) const BROWSER_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/97.0.4692.71 Safari/537.36";
+
+// 抓取微博的方式。目前有两种实现:
+// * WeiboClient 通过 WebDriver 驱动浏览器，支持交互式登录
+// * HttpWeiboClient 直接请求接口，依赖已保存的 cookie，无需浏览器
+#[async_trait]
+pub trait WeiboCrawler: Send + Sync {
+    async fn get_posts_by_page(
+        &self,
+        list: PostList,
+        page_id: u32,
+    ) -> Result<Vec<CrawledPost>, anyhow::Error>;
+
+    // 获取长微博的全文
+    async fn get_long_text(&self, mblogid: &str) -> Result<Option<String>, anyhow::Error>;
+
+    // 导出当前的 cookie，以便下次复用登录态
+    async fn session_cookies(&self) -> Result<Vec<SessionCookie>, anyhow::Error>;
+
+    async fn close(self: Box<Self>) -> Result<(), anyhow::Error>;
+}
+
+#[derive(Debug, Clone)]
+pub struct LoginConfig {
+    // 等待用户完成登录(比如扫码)的最长时间
+    pub timeout: Duration,
+    // 检查是否已登录的时间间隔
+    pub poll_interval: Duration,
+    // 上次登录后保存的 cookie。若仍然有效，则无需再次登录
+    pub saved_cookies: Vec<SessionCookie>,
+}
+
+impl Default for LoginConfig {
+    fn default() -> LoginConfig {
+        LoginConfig {
+            timeout: Duration::from_secs(120),
+            poll_interval: Duration::from_secs(2),
+            saved_cookies: vec![],
+        }
+    }
+}
+
+// 登录态的 cookie，保存在 settings 表中，供之后的 crawl 复用
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SessionCookie {
+    pub name: String,
+    pub value: String,
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    pub secure: Option<bool>,
+    pub http_only: Option<bool>,
+    // 过期时间(unix 时间戳)，None 表示会话 cookie
+    pub expiry: Option<i64>,
+}
+
+impl SessionCookie {
+    pub fn is_expired(&self) -> bool {
+        match self.expiry {
+            Some(expiry) => expiry <= Utc::now().timestamp(),
+            None => false,
+        }
+    }
+
+    // 解析浏览器中复制出来的 Cookie 请求头，形如 "SUB=xxx; SUBP=yyy"
+    pub fn parse_header(header: &str) -> Result<Vec<SessionCookie>, anyhow::Error> {
+        let mut cookies = vec![];
+        for pair in header.split(';') {
+            let pair = pair.trim();
+            if pair.is_empty() {
+                continue;
+            }
+            let (name, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow::format_err!("invalid cookie: {}", pair))?;
+            cookies.push(SessionCookie {
+                name: name.trim().to_string(),
+                value: value.trim().to_string(),
+                domain: Some(".weibo.com".to_string()),
+                path: Some("/".to_string()),
+                secure: None,
+                http_only: None,
+                expiry: None,
+            });
+        }
+        Ok(cookies)
+    }
+}
+
+// 可以逐页抓取的微博列表
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PostList {
+    // 登录用户的收藏
+    Favorites,
+    // 某个用户发布的微博，包括登录用户自己
+    Timeline(i64),
+    // 某个用户赞过的微博
+    Likes(i64),
+}
+
+impl PostList {
+    fn page_url(&self, base_url: &str, page_id: u32) -> String {
+        match self {
+            PostList::Favorites => fav_page_url(base_url, page_id),
+            PostList::Timeline(uid) => format!(
+                "{}/ajax/statuses/mymblog?uid={}&page={}&feature=0",
+                base_url, uid, page_id
+            ),
+            PostList::Likes(uid) => format!(
+                "{}/ajax/statuses/likelist?uid={}&page={}",
+                base_url, uid, page_id
+            ),
+        }
+    }
+}
+
+// 抓取到的一条微博。raw 是接口返回的原始 JSON，保存下来，
+// 以便改进 RawPost::normalize 之后不必重新抓取即可重新生成 Post
+#[derive(Debug, Clone)]
+pub struct CrawledPost {
+    pub post: Post,
+    pub raw: Value,
+}
+
+impl CrawledPost {
+    pub fn from_raw(raw: Value) -> Result<CrawledPost, anyhow::Error> {
+        let raw_post: RawPost = serde_json::from_value(raw.clone())?;
+        Ok(CrawledPost {
+            post: raw_post.normalize(),
+            raw,
+        })
+    }
+}
+
+// 微博列表接口的返回。未登录时，ok 不为 1，且没有 data 字段。
+// 收藏接口的 data 即是微博的数组，时间线及赞过的微博的接口，微博的数组在 data.list 中
+#[derive(Deserialize)]
+pub(crate) struct PostListResponse {
+    #[serde(default)]
+    ok: i32,
+    #[serde(default)]
+    data: Value,
+}
+
+impl PostListResponse {
+    pub(crate) fn is_ok(&self) -> bool {
+        self.ok == 1
+    }
+
+    pub(crate) fn into_crawled_posts(self) -> Result<Vec<CrawledPost>, anyhow::Error> {
+        let raw_posts = match self.data {
+            Value::Array(raw_posts) => raw_posts,
+            Value::Object(mut data) => match data.remove("list") {
+                Some(Value::Array(raw_posts)) => raw_posts,
+                Some(_) | None => vec![],
+            },
+            Value::Null => vec![],
+            _ => return Err(anyhow::format_err!("unexpected data in post list response")),
+        };
+        raw_posts.into_iter().map(CrawledPost::from_raw).collect()
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LongTextResponse {
+    #[serde(default)]
+    ok: i32,
+    data: Option<RawLongText>,
+}
+
+impl LongTextResponse {
+    fn into_long_text(self) -> Result<Option<String>, anyhow::Error> {
+        if self.ok != 1 {
+            return Err(anyhow::format_err!("failed to get long text"));
+        }
+        Ok(self.data.and_then(|data| data.normalize()))
+    }
+}
+
+fn fav_page_url(base_url: &str, page_id: u32) -> String {
+    format!("{}/ajax/favorites/all_fav?page={}", base_url, page_id)
+}
+
+fn long_text_url(base_url: &str, mblogid: &str) -> String {
+    format!("{}/ajax/statuses/longtext?id={}", base_url, mblogid)
+}
+
+// 获取此微博及被转发微博的长微博全文，返回是否获取到了全文。
+// 跳过 unavailable_ids 中的微博；接口没有返回全文的微博加入 missing_ids，由调用方记录下来，之后不再请求。
+// 请求失败时只记录日志，被转发的微博获取失败时，此微博已获取的全文仍然保留
+pub async fn fill_long_text(
+    weibo_client: &dyn WeiboCrawler,
+    post: &mut Post,
+    unavailable_ids: &HashSet<i64>,
+    missing_ids: &mut Vec<i64>,
+) -> bool {
+    let mut filled = fill_post_long_text(weibo_client, post, unavailable_ids, missing_ids).await;
+    if let Some(retweeted_post) = &mut post.retweeted_post {
+        filled |=
+            fill_post_long_text(weibo_client, retweeted_post, unavailable_ids, missing_ids).await;
+    }
+    filled
+}
+
+// 只处理 post 本身，不包括被转发的微博
+async fn fill_post_long_text(
+    weibo_client: &dyn WeiboCrawler,
+    post: &mut Post,
+    unavailable_ids: &HashSet<i64>,
+    missing_ids: &mut Vec<i64>,
+) -> bool {
+    if !post.is_long_text || post.long_text.is_some() || unavailable_ids.contains(&post.id) {
+        return false;
+    }
+    match weibo_client.get_long_text(&post.mblogid).await {
+        Ok(Some(long_text)) => {
+            post.long_text = Some(long_text);
+            true
+        }
+        Ok(None) => {
+            warn!("long text unavailable, id: {}", post.id);
+            missing_ids.push(post.id);
+            false
+        }
+        Err(e) => {
+            warn!("failed to get long text, id: {}, error: {}", post.id, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_post_list_response() -> Result<(), anyhow::Error> {
+        let text: Value = serde_json::from_str(include_str!("../../test_data/text.json"))?;
+        let responses = [
+            serde_json::json!({"ok": 1, "data": [text]}),
+            serde_json::json!({"ok": 1, "data": {"list": [text], "total": 1}}),
+        ];
+        for response in responses {
+            let res: PostListResponse = serde_json::from_value(response)?;
+            assert!(res.is_ok());
+            let posts = res.into_crawled_posts()?;
+            assert_eq!(posts.len(), 1);
+            assert_eq!(posts[0].raw, text);
+        }
+
+        let res: PostListResponse = serde_json::from_str(r#"{"ok": -100}"#)?;
+        assert!(!res.is_ok());
+        assert!(res.into_crawled_posts()?.is_empty());
+        assert_eq!(
+            PostList::Likes(42).page_url("https://weibo.com", 2),
+            "https://weibo.com/ajax/statuses/likelist?uid=42&page=2"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cookie_header() -> Result<(), anyhow::Error> {
+        let cookies = SessionCookie::parse_header("SUB=abc; SUBP=d=e;")?;
+        let pairs: Vec<(&str, &str)> = cookies
+            .iter()
+            .map(|c| (c.name.as_str(), c.value.as_str()))
+            .collect();
+        assert_eq!(pairs, vec![("SUB", "abc"), ("SUBP", "d=e")]);
+        assert!(SessionCookie::parse_header("SUB").is_err());
+        Ok(())
+    }
+}
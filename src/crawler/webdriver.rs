@@ -0,0 +1,375 @@
+use crate::chromedriver::{start_chromedriver, ChromeDriverProcess};
+use crate::crawler::{
+    long_text_url, CrawledPost, LoginConfig, LongTextResponse, PostList, PostListResponse,
+    SessionCookie, WeiboCrawler, WEIBO_URL,
+};
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use std::time::Instant;
+use thirtyfour::prelude::*;
+
+// 在页面中同步请求收藏接口，用于判断是否已登录。
+// 页面不在 weibo.com 域下(比如还在登录页)时，请求会因跨域而失败，返回 null。
+const LOGIN_PROBE_SCRIPT: &str = r#"
+    try {
+        const xhr = new XMLHttpRequest();
+        xhr.open("GET", "/ajax/favorites/all_fav?page=1", false);
+        xhr.send();
+        return xhr.status === 200 ? xhr.responseText : null;
+    } catch (e) {
+        return null;
+    }
+"#;
+
+pub struct WeiboClient {
+    #[allow(unused)]
+    chromedriver: Option<ChromeDriverProcess>,
+    driver: WebDriver,
+    base_url: String,
+}
+
+impl WeiboClient {
+    pub async fn login(login_config: &LoginConfig) -> Result<WeiboClient, anyhow::Error> {
+        let chromedriver = start_chromedriver(4444)?;
+        let mut client =
+            WeiboClient::connect(&chromedriver.server_url(), WEIBO_URL, login_config).await?;
+        client.chromedriver = Some(chromedriver);
+        Ok(client)
+    }
+
+    // 连接到已启动的 WebDriver，打开 base_url 并等待用户登录
+    pub async fn connect(
+        server_url: &str,
+        base_url: &str,
+        login_config: &LoginConfig,
+    ) -> Result<WeiboClient, anyhow::Error> {
+        let cap = DesiredCapabilities::chrome();
+        let driver = WebDriver::new(server_url, cap).await?;
+        let client = WeiboClient {
+            chromedriver: None,
+            driver,
+            base_url: base_url.to_string(),
+        };
+        client.driver.goto(&client.base_url).await?;
+
+        if !login_config.saved_cookies.is_empty() {
+            if client.restore_session(&login_config.saved_cookies).await? {
+                info!("restored saved login session");
+                return Ok(client);
+            }
+            info!("saved login session is no longer valid");
+        }
+
+        info!(
+            "waiting for login, at most {} seconds",
+            login_config.timeout.as_secs()
+        );
+        if let Err(e) = client.wait_for_login(login_config).await {
+            // 返回登录失败的原因，而不是关闭浏览器时的错误
+            if let Err(quit_error) = client.driver.quit().await {
+                warn!("failed to quit webdriver: {}", quit_error);
+            }
+            return Err(e);
+        }
+        info!("login succeeded");
+        Ok(client)
+    }
+
+    async fn restore_session(&self, cookies: &[SessionCookie]) -> Result<bool, anyhow::Error> {
+        for session_cookie in cookies {
+            if session_cookie.is_expired() {
+                continue;
+            }
+            let mut cookie = Cookie::new(session_cookie.name.clone(), session_cookie.value.clone());
+            if let Some(domain) = &session_cookie.domain {
+                cookie.set_domain(domain.clone());
+            }
+            if let Some(path) = &session_cookie.path {
+                cookie.set_path(path.clone());
+            }
+            cookie.set_secure(session_cookie.secure);
+            cookie.set_http_only(session_cookie.http_only);
+            // 个别 cookie 无效时跳过，恢复不了登录状态的话，会退回到手动登录
+            if let Err(e) = self.driver.add_cookie(cookie).await {
+                warn!("failed to restore cookie {}: {}", session_cookie.name, e);
+            }
+        }
+        self.driver.goto(&self.base_url).await?;
+        self.is_logged_in().await
+    }
+
+    async fn wait_for_login(&self, login_config: &LoginConfig) -> Result<(), anyhow::Error> {
+        let deadline = Instant::now() + login_config.timeout;
+        loop {
+            match self.is_logged_in().await {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(e) => debug!("login probe failed: {}", e),
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow::format_err!(
+                    "login not completed within {} seconds",
+                    login_config.timeout.as_secs()
+                ));
+            }
+            tokio::time::sleep(login_config.poll_interval).await;
+        }
+    }
+
+    // 在浏览器中打开接口，JSON 会显示在 <pre> 元素中
+    async fn get_json(&self, url: &str) -> Result<String, anyhow::Error> {
+        self.driver.goto(url).await?;
+        let content = self.driver.find(By::Css("pre")).await?.text().await?;
+        Ok(content)
+    }
+
+    async fn is_logged_in(&self) -> Result<bool, anyhow::Error> {
+        let ret = self.driver.execute(LOGIN_PROBE_SCRIPT, vec![]).await?;
+        let content: Option<String> = ret.convert()?;
+        let logged_in = match content {
+            Some(content) => serde_json::from_str::<PostListResponse>(&content)
+                .map(|res| res.is_ok())
+                .unwrap_or(false),
+            None => false,
+        };
+        Ok(logged_in)
+    }
+}
+
+#[async_trait]
+impl WeiboCrawler for WeiboClient {
+    async fn get_posts_by_page(
+        &self,
+        list: PostList,
+        page_id: u32,
+    ) -> Result<Vec<CrawledPost>, anyhow::Error> {
+        let content = self
+            .get_json(&list.page_url(&self.base_url, page_id))
+            .await?;
+        let res: PostListResponse = serde_json::from_str(&content)?;
+        // 登录态失效时 ok 不为 1，不能当作已经没有更多微博
+        if !res.is_ok() {
+            return Err(anyhow::format_err!(
+                "failed to get page {} of {:?}",
+                page_id,
+                list
+            ));
+        }
+        res.into_crawled_posts()
+    }
+
+    async fn get_long_text(&self, mblogid: &str) -> Result<Option<String>, anyhow::Error> {
+        let content = self
+            .get_json(&long_text_url(&self.base_url, mblogid))
+            .await?;
+        let res: LongTextResponse = serde_json::from_str(&content)?;
+        res.into_long_text()
+    }
+
+    async fn session_cookies(&self) -> Result<Vec<SessionCookie>, anyhow::Error> {
+        let cookies = self.driver.get_all_cookies().await?;
+        let session_cookies = cookies
+            .iter()
+            .map(|cookie| SessionCookie {
+                name: cookie.name().to_string(),
+                value: cookie.value().to_string(),
+                domain: cookie.domain().map(String::from),
+                path: cookie.path().map(String::from),
+                secure: cookie.secure(),
+                http_only: cookie.http_only(),
+                expiry: cookie
+                    .expires()
+                    .and_then(|e| e.datetime())
+                    .map(|dt| dt.unix_timestamp()),
+            })
+            .collect();
+        Ok(session_cookies)
+    }
+
+    async fn close(self: Box<Self>) -> Result<(), anyhow::Error> {
+        self.driver.quit().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{FakeHttpServer, FakeRequest, FakeResponse};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    // 模拟 WebDriver：前 logged_in_after 次登录检查返回未登录，之后返回已登录。
+    // 另外，设置了名为 SUB 的 cookie 之后，也视为已登录。
+    fn fake_webdriver(logged_in_after: usize) -> (FakeHttpServer, Arc<AtomicUsize>) {
+        let probes = Arc::new(AtomicUsize::new(0));
+        let counter = probes.clone();
+        let cookies = Arc::new(Mutex::new(vec![]));
+        let server = FakeHttpServer::start(move |req: &FakeRequest| {
+            if req.method == "POST" && req.path == "/session" {
+                return FakeResponse::json(json!({
+                    "value": {"sessionId": "fake", "capabilities": {}}
+                }));
+            }
+            if req.method == "GET" && req.path.ends_with("/url") {
+                return FakeResponse::json(json!({ "value": "about:blank" }));
+            }
+            if req.path.ends_with("/cookie") {
+                let mut cookies = cookies.lock().unwrap();
+                if req.method == "POST" {
+                    let body: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+                    // 名为 BAD 的 cookie 模拟 WebDriver 拒绝的 cookie
+                    if body["cookie"]["name"] == "BAD" {
+                        let mut res = FakeResponse::json(json!({
+                            "value": {
+                                "error": "invalid cookie domain",
+                                "message": "invalid cookie domain",
+                                "stacktrace": ""
+                            }
+                        }));
+                        res.status = 400;
+                        return res;
+                    }
+                    cookies.push(body["cookie"].clone());
+                    return FakeResponse::json(json!({ "value": null }));
+                }
+                return FakeResponse::json(json!({ "value": *cookies }));
+            }
+            // 页面中 <pre> 的内容，即接口返回的 JSON。没有 SUB cookie 时视为登录态已失效
+            if req.method == "POST" && req.path.ends_with("/element") {
+                return FakeResponse::json(json!({
+                    "value": {"element-6066-11e4-a52e-4f735466cecf": "pre"}
+                }));
+            }
+            if req.path.ends_with("/element/pre/text") {
+                let has_session_cookie = cookies
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .any(|cookie| cookie["name"] == "SUB");
+                let content = if has_session_cookie {
+                    json!({"ok": 1, "data": []}).to_string()
+                } else {
+                    json!({"ok": -100, "url": "https://weibo.com/login.php"}).to_string()
+                };
+                return FakeResponse::json(json!({ "value": content }));
+            }
+            if req.path.ends_with("/execute/sync") {
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                let has_session_cookie = cookies
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .any(|cookie| cookie["name"] == "SUB");
+                let content = if n >= logged_in_after || has_session_cookie {
+                    json!({"ok": 1, "data": []}).to_string()
+                } else {
+                    json!({"ok": -100, "url": "https://weibo.com/login.php"}).to_string()
+                };
+                return FakeResponse::json(json!({ "value": content }));
+            }
+            FakeResponse::json(json!({ "value": null }))
+        });
+        (server, probes)
+    }
+
+    fn login_config(timeout_millis: u64) -> LoginConfig {
+        LoginConfig {
+            timeout: Duration::from_millis(timeout_millis),
+            poll_interval: Duration::from_millis(10),
+            saved_cookies: vec![],
+        }
+    }
+
+    fn session_cookie(name: &str, expiry: Option<i64>) -> SessionCookie {
+        SessionCookie {
+            name: name.to_string(),
+            value: "value".to_string(),
+            domain: Some(".weibo.com".to_string()),
+            path: Some("/".to_string()),
+            secure: Some(true),
+            http_only: None,
+            expiry,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_login_waits_until_logged_in() -> Result<(), anyhow::Error> {
+        let (server, probes) = fake_webdriver(3);
+        let client = WeiboClient::connect(&server.url(), WEIBO_URL, &login_config(5000)).await?;
+        assert_eq!(probes.load(Ordering::SeqCst), 4);
+        Box::new(client).close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_login_timeout() {
+        let (server, _probes) = fake_webdriver(usize::MAX);
+        let res = WeiboClient::connect(&server.url(), WEIBO_URL, &login_config(100)).await;
+        let err = res.err().expect("login should time out");
+        assert!(err.to_string().contains("login not completed"));
+    }
+
+    #[tokio::test]
+    async fn test_login_with_saved_cookies() -> Result<(), anyhow::Error> {
+        let (server, probes) = fake_webdriver(usize::MAX);
+        let mut config = login_config(100);
+        config.saved_cookies = vec![session_cookie("SUB", None), session_cookie("OLD", Some(1))];
+        let client = WeiboClient::connect(&server.url(), WEIBO_URL, &config).await?;
+        assert_eq!(probes.load(Ordering::SeqCst), 1);
+
+        // 已过期的 cookie 不会被恢复
+        let cookies = client.session_cookies().await?;
+        let names: Vec<&str> = cookies.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["SUB"]);
+        assert_eq!(cookies[0].domain.as_deref(), Some(".weibo.com"));
+        Box::new(client).close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_login_with_invalid_saved_cookies() -> Result<(), anyhow::Error> {
+        let (server, probes) = fake_webdriver(2);
+        let mut config = login_config(5000);
+        config.saved_cookies = vec![session_cookie("OTHER", None)];
+        let client = WeiboClient::connect(&server.url(), WEIBO_URL, &config).await?;
+        assert_eq!(probes.load(Ordering::SeqCst), 3);
+        Box::new(client).close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_login_skips_rejected_saved_cookies() -> Result<(), anyhow::Error> {
+        let (server, probes) = fake_webdriver(usize::MAX);
+        let mut config = login_config(100);
+        config.saved_cookies = vec![session_cookie("BAD", None), session_cookie("SUB", None)];
+        let client = WeiboClient::connect(&server.url(), WEIBO_URL, &config).await?;
+        assert_eq!(probes.load(Ordering::SeqCst), 1);
+        Box::new(client).close().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_posts_by_page_with_expired_session() -> Result<(), anyhow::Error> {
+        let (server, _probes) = fake_webdriver(usize::MAX);
+        let mut config = login_config(100);
+        config.saved_cookies = vec![session_cookie("SUB", None)];
+        let client = WeiboClient::connect(&server.url(), WEIBO_URL, &config).await?;
+        assert!(client
+            .get_posts_by_page(PostList::Favorites, 1)
+            .await?
+            .is_empty());
+        Box::new(client).close().await?;
+
+        let (server, _probes) = fake_webdriver(0);
+        let client = WeiboClient::connect(&server.url(), WEIBO_URL, &login_config(100)).await?;
+        assert!(client
+            .get_posts_by_page(PostList::Favorites, 1)
+            .await
+            .is_err());
+        Box::new(client).close().await?;
+        Ok(())
+    }
+}
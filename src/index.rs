@@ -1,10 +1,25 @@
-use crate::weibo::post::Post;
+use crate::query::{parse_query, SearchScope};
+use crate::render::escape_html;
+use crate::weibo::post::{weibo_timezone, MediaAsset, MediaType, Post, PostSource, PostVisibility};
+use chrono::{DateTime, FixedOffset, TimeZone};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ops::Range;
 use std::path::Path;
-use tantivy::collector::TopDocs;
+use tantivy::collector::{Count, FacetCollector, TopDocs};
 use tantivy::directory::MmapDirectory;
-use tantivy::query::QueryParser;
+use tantivy::fastfield::FastFieldReader;
+use tantivy::query::{
+    AllQuery, BooleanQuery, BoostQuery, Occur, PhraseQuery, Query, QueryParser, QueryParserError,
+    RangeQuery, TermQuery,
+};
 use tantivy::schema::*;
-use tantivy::{Index, ReloadPolicy};
+use tantivy::store::Compressor;
+use tantivy::{
+    DocAddress, DocId, Index, IndexBuilder, IndexSettings, IndexWriter, LeasedItem, ReloadPolicy,
+    Searcher, SegmentReader,
+};
+use tantivy::{Snippet, SnippetGenerator};
 
 pub struct WeiboIndexer {
     index: Index,
@@ -12,46 +27,74 @@ pub struct WeiboIndexer {
 
 impl WeiboIndexer {
     pub fn with_index_dir<P: AsRef<Path>>(dir: P) -> Result<WeiboIndexer, anyhow::Error> {
-        let jieba_tokenizer = tantivy_jieba::JiebaTokenizer {};
-        let text_field_indexing = TextFieldIndexing::default()
-            .set_tokenizer("jieba")
-            .set_index_option(IndexRecordOption::WithFreqsAndPositions);
-        let text_options = TextOptions::default()
-            .set_indexing_options(text_field_indexing)
-            .set_stored();
-
-        let mut schema_builder = Schema::builder();
-        schema_builder.add_text_field("url", STRING | STORED);
-        schema_builder.add_text_field("user", STRING | STORED);
-        schema_builder.add_text_field("text", text_options.clone());
-        schema_builder.add_u64_field("media_type", IntOptions::default().set_indexed());
-        schema_builder.add_text_field("retweeted_user", STRING | STORED);
-        schema_builder.add_text_field("retweeted_text", text_options);
-        let schema = schema_builder.build();
-
         let dir = MmapDirectory::open(dir)?;
-        let index = Index::open_or_create(dir, schema)?;
+        let index = index_builder().open_or_create(dir)?;
+        Ok(WeiboIndexer::with_index(index))
+    }
+
+    pub fn in_ram() -> WeiboIndexer {
+        let index = index_builder()
+            .create_in_ram()
+            .expect("creating index in ram should never fail");
+        WeiboIndexer::with_index(index)
+    }
+
+    fn with_index(index: Index) -> WeiboIndexer {
+        let jieba_tokenizer = tantivy_jieba::JiebaTokenizer {};
         index.tokenizers().register("jieba", jieba_tokenizer);
-        Ok(WeiboIndexer { index })
+        WeiboIndexer { index }
     }
 
     pub fn schema(&self) -> Schema {
         self.index.schema()
     }
 
+    // 新增或更新微博。已存在于索引中的同 id 文档会先被删除。
     pub fn index_weibo_posts(&self, posts: &[Post]) -> Result<(), anyhow::Error> {
-        let mut index_writer = self.index.writer(50_000_000)?;
+        let mut index_writer = self.writer()?;
         let schema = self.schema();
+        let id_field = schema.get_field("id").unwrap();
 
         for post in posts {
             let mut doc = Document::default();
+            doc.add_i64(id_field, post.id);
+            doc.add_u64(schema.get_field("version").unwrap(), post_version(post)?);
+            doc.add_i64(
+                schema.get_field("created_at").unwrap(),
+                post.created_at.timestamp(),
+            );
             doc.add_text(schema.get_field("url").unwrap(), post.url());
             doc.add_text(schema.get_field("user").unwrap(), &post.user.screen_name);
-            doc.add_text(schema.get_field("text").unwrap(), &post.text_raw);
+            doc.add_text(schema.get_field("text").unwrap(), post.full_text());
             doc.add_u64(
                 schema.get_field("media_type").unwrap(),
                 post.media_type() as u8 as u64,
             );
+            doc.add_u64(
+                schema.get_field("picture_count").unwrap(),
+                picture_count(post) as u64,
+            );
+            doc.add_u64(
+                schema.get_field("video_duration").unwrap(),
+                video_duration(post) as u64,
+            );
+            for media_url in media_urls(post) {
+                doc.add_text(schema.get_field("media_url").unwrap(), media_url);
+            }
+            doc.add_u64(
+                schema.get_field("visibility").unwrap(),
+                post.overall_visibility() as u8 as u64,
+            );
+            if let Some(faved_at) = post.faved_at {
+                doc.add_i64(schema.get_field("faved_at").unwrap(), faved_at.timestamp());
+            }
+            doc.add_u64(
+                schema.get_field("unfaved").unwrap(),
+                post.unfaved_at.is_some() as u64,
+            );
+            for source in &post.sources {
+                doc.add_text(schema.get_field("source").unwrap(), source.tag());
+            }
             if let Some(retweeted_post) = &post.retweeted_post {
                 doc.add_text(
                     schema.get_field("retweeted_user").unwrap(),
@@ -59,69 +102,777 @@ impl WeiboIndexer {
                 );
                 doc.add_text(
                     schema.get_field("retweeted_text").unwrap(),
-                    &retweeted_post.text_raw,
+                    retweeted_post.full_text(),
                 );
             }
 
+            for facet in post_facets(post) {
+                doc.add_facet(schema.get_field("facet").unwrap(), facet);
+            }
+
+            index_writer.delete_term(Term::from_field_i64(id_field, post.id));
             index_writer.add_document(doc);
         }
         index_writer.commit()?;
         Ok(())
     }
 
-    pub fn search(
-        &self,
-        params: &WeiboSearchParams,
-        limit: usize,
-    ) -> Result<Vec<SearchedWeiboPost>, anyhow::Error> {
-        let mut query_str = String::new();
+    pub fn delete_posts(&self, post_ids: &[i64]) -> Result<(), anyhow::Error> {
+        let mut index_writer = self.writer()?;
+        let id_field = self.schema().get_field("id").unwrap();
+        for post_id in post_ids {
+            index_writer.delete_term(Term::from_field_i64(id_field, *post_id));
+        }
+        index_writer.commit()?;
+        Ok(())
+    }
+
+    // 返回索引中所有微博的 id 及其内容版本(见 post_version)，用于增量索引。
+    pub fn indexed_post_versions(&self) -> Result<HashMap<i64, u64>, anyhow::Error> {
+        let schema = self.schema();
+        let id_field = schema.get_field("id").unwrap();
+        let version_field = schema.get_field("version").unwrap();
+
+        let reader = self
+            .index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+        let searcher = reader.searcher();
+
+        let mut versions = HashMap::new();
+        for segment_reader in searcher.segment_readers() {
+            let ids = segment_reader.fast_fields().i64(id_field)?;
+            let post_versions = segment_reader.fast_fields().u64(version_field)?;
+            for doc in segment_reader.doc_ids_alive() {
+                versions.insert(ids.get(doc), post_versions.get(doc));
+            }
+        }
+        Ok(versions)
+    }
+
+    fn writer(&self) -> Result<IndexWriter, anyhow::Error> {
+        let index_writer = self.index.writer(50_000_000)?;
+        Ok(index_writer)
+    }
+
+    pub fn search(&self, params: &WeiboSearchParams) -> Result<SearchPage, anyhow::Error> {
+        let schema = self.schema();
+        let created_at_field = schema.get_field("created_at").unwrap();
+        let query = self.build_query(params)?;
+
+        // 游标只用于按时间排序的搜索，相关度在索引更新后会变化
+        let cursor = match &params.cursor {
+            Some(_) if params.sort == SortOrder::Relevance => {
+                return Err(anyhow::format_err!(
+                    "cursor can only be used when sorting by time"
+                ));
+            }
+            Some(cursor) => Some(SearchCursor::parse(cursor)?),
+            None => None,
+        };
+        let paged_query: Box<dyn Query> = match cursor {
+            Some(cursor) => Box::new(BooleanQuery::new(vec![
+                (Occur::Must, query.box_clone()),
+                (Occur::Must, self.cursor_query(cursor, params.sort)),
+            ])),
+            None => query.box_clone(),
+        };
+
+        let searcher = self.searcher()?;
+        let total = searcher.search(&query, &Count)?;
+        // 游标之前的微博数，用于计算本页的位置
+        let skipped = match cursor {
+            Some(_) => total - searcher.search(&paged_query, &Count)?,
+            None => 0,
+        };
+
+        let top_docs = TopDocs::with_limit(params.limit.max(1)).and_offset(params.offset);
+        // 只有按相关度排序时才有 score
+        let mut doc_addresses: Vec<(Option<f32>, DocAddress)> = match params.sort {
+            SortOrder::Relevance => searcher
+                .search(&paged_query, &top_docs)?
+                .into_iter()
+                .map(|(score, doc_address)| (Some(score), doc_address))
+                .collect(),
+            // 同一秒发布的微博再按 id 排序，翻页时顺序才是确定的
+            SortOrder::Newest | SortOrder::Oldest => {
+                let newest_first = params.sort == SortOrder::Newest;
+                let id_field = schema.get_field("id").unwrap();
+                let collector = top_docs.custom_score(move |segment_reader: &SegmentReader| {
+                    let created_at_reader =
+                        segment_reader.fast_fields().i64(created_at_field).unwrap();
+                    let id_reader = segment_reader.fast_fields().i64(id_field).unwrap();
+                    move |doc: DocId| {
+                        let key = (created_at_reader.get(doc), id_reader.get(doc));
+                        if newest_first {
+                            key
+                        } else {
+                            (-key.0, -key.1)
+                        }
+                    }
+                });
+                searcher
+                    .search(&paged_query, &collector)?
+                    .into_iter()
+                    .map(|(_key, doc_address)| (None, doc_address))
+                    .collect()
+            }
+        };
+        doc_addresses.truncate(params.limit);
+
+        // 有搜索词时才生成摘要。tantivy 以字节计算摘要长度，中文字符占 3 个字节
+        let mut snippet_generators = vec![];
+        if params.query.is_some() && params.snippet_len > 0 {
+            for field_name in ["text", "retweeted_text"] {
+                let field = schema.get_field(field_name).unwrap();
+                let mut generator = SnippetGenerator::create(&searcher, &*query, field)?;
+                generator.set_max_num_chars(params.snippet_len * 3);
+                snippet_generators.push((field_name, generator));
+            }
+        }
+
+        let mut posts = vec![];
+        for (score, doc_address) in doc_addresses {
+            let retrieved_doc = searcher.doc(doc_address)?;
+            let mut post = SearchedWeiboPost::from_doc(&schema, &retrieved_doc);
+            post.score = score;
+            post.snippet = snippet_generators
+                .iter()
+                .find_map(|(field_name, generator)| {
+                    let snippet = generator.snippet_from_doc(&retrieved_doc);
+                    if snippet.highlighted().is_empty() {
+                        None
+                    } else {
+                        Some(PostSnippet::from_snippet(field_name, &snippet))
+                    }
+                });
+            posts.push(post);
+        }
+
+        let offset = skipped + params.offset;
+        let next_cursor = match posts.last() {
+            Some(last) if params.sort != SortOrder::Relevance && offset + posts.len() < total => {
+                let cursor = SearchCursor {
+                    created_at: last.created_at.timestamp(),
+                    id: last.id,
+                };
+                Some(cursor.to_string())
+            }
+            _ => None,
+        };
+        Ok(SearchPage {
+            total,
+            offset,
+            posts,
+            next_cursor,
+        })
+    }
+
+    // 排在游标之后的微博
+    fn cursor_query(&self, cursor: SearchCursor, sort: SortOrder) -> Box<dyn Query> {
+        let schema = self.schema();
+        let created_at_field = schema.get_field("created_at").unwrap();
+        let id_field = schema.get_field("id").unwrap();
+        let (created_at_range, id_range) = match sort {
+            SortOrder::Oldest => (cursor.created_at + 1..i64::MAX, cursor.id + 1..i64::MAX),
+            _ => (i64::MIN..cursor.created_at, i64::MIN..cursor.id),
+        };
+        let same_second = BooleanQuery::new(vec![
+            (
+                Occur::Must,
+                Box::new(RangeQuery::new_i64(
+                    created_at_field,
+                    cursor.created_at..cursor.created_at + 1,
+                )) as Box<dyn Query>,
+            ),
+            (
+                Occur::Must,
+                Box::new(RangeQuery::new_i64(id_field, id_range)),
+            ),
+        ]);
+        Box::new(BooleanQuery::new(vec![
+            (
+                Occur::Should,
+                Box::new(RangeQuery::new_i64(created_at_field, created_at_range)),
+            ),
+            (Occur::Should, Box::new(same_second)),
+        ]))
+    }
+
+    // 搜索条件对应的查询，不包括翻页
+    fn build_query(&self, params: &WeiboSearchParams) -> Result<Box<dyn Query>, anyhow::Error> {
+        let schema = self.schema();
+        let created_at_field = schema.get_field("created_at").unwrap();
+
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![];
         if let Some(query) = &params.query {
-            let text_query = format!("text:{}", query);
-            query_str.push_str(&text_query);
+            let text_query = if params.advanced {
+                Some(self.advanced_query(params, query)?)
+            } else {
+                self.text_query(params, query)?
+            };
+            if let Some(text_query) = text_query {
+                clauses.push((Occur::Must, text_query));
+            }
         }
         if let Some(media_type) = params.media_type {
-            let media_query = format!(" media_type:{}", media_type);
-            query_str.push_str(&media_query);
+            let field = schema.get_field("media_type").unwrap();
+            let term = Term::from_field_u64(field, media_type as u8 as u64);
+            clauses.push((Occur::Must, term_query(term)));
+        }
+        if let Some(min_pictures) = params.min_pictures {
+            let field = schema.get_field("picture_count").unwrap();
+            let range_query = RangeQuery::new_u64(field, min_pictures as u64..u64::MAX);
+            clauses.push((Occur::Must, Box::new(range_query)));
+        }
+        if let Some(secs) = params.video_longer_than {
+            let field = schema.get_field("video_duration").unwrap();
+            let range_query = RangeQuery::new_u64(field, secs as u64 + 1..u64::MAX);
+            clauses.push((Occur::Must, Box::new(range_query)));
+        }
+        if let Some(retweet) = params.retweet {
+            let field = schema.get_field("facet").unwrap();
+            let kind = if retweet { "retweet" } else { "original" };
+            let facet = Facet::from_path(["kind", kind]);
+            clauses.push((Occur::Must, term_query(Term::from_facet(field, &facet))));
+        }
+        if let Some(visibility) = params.visibility {
+            let field = schema.get_field("visibility").unwrap();
+            let term = Term::from_field_u64(field, visibility as u8 as u64);
+            clauses.push((Occur::Must, term_query(term)));
+        }
+        if let Some(source) = params.source {
+            let field = schema.get_field("source").unwrap();
+            let term = Term::from_field_text(field, &source.tag());
+            clauses.push((Occur::Must, term_query(term)));
         }
         if let Some(user) = &params.user {
-            let user_query = format!(" user:{}", user);
-            query_str.push_str(&user_query);
+            let field = schema.get_field("user").unwrap();
+            clauses.push((Occur::Must, term_query(Term::from_field_text(field, user))));
+        }
+        if params.since.is_some() || params.until.is_some() {
+            let since = params.since.map_or(i64::MIN, |dt| dt.timestamp());
+            let until = params.until.map_or(i64::MAX, |dt| dt.timestamp());
+            let range_query = RangeQuery::new_i64(created_at_field, since..until);
+            clauses.push((Occur::Must, Box::new(range_query)));
+        }
+        if let Some(faved_since) = params.faved_since {
+            let field = schema.get_field("faved_at").unwrap();
+            let range_query = RangeQuery::new_i64(field, faved_since.timestamp()..i64::MAX);
+            clauses.push((Occur::Must, Box::new(range_query)));
+        }
+        // 以 MustNot 排除，不影响相关度
+        if !params.include_unfaved {
+            let field = schema.get_field("unfaved").unwrap();
+            let term = Term::from_field_u64(field, 1);
+            clauses.push((Occur::MustNot, term_query(term)));
         }
+        // 只有 MustNot 的 BooleanQuery 不匹配任何文档
+        if clauses.iter().all(|(occur, _)| *occur == Occur::MustNot) {
+            clauses.push((Occur::Must, Box::new(AllQuery)));
+        }
+        let query: Box<dyn Query> = Box::new(BooleanQuery::new(clauses));
+        Ok(query)
+    }
 
-        let query_parser = QueryParser::for_index(&self.index, vec![]);
-        let query = query_parser.parse_query(&query_str)?;
+    // 所有匹配的微博(不限于当前页)按作者、媒体类型、是否转发及发布月份的统计
+    pub fn facets(
+        &self,
+        params: &WeiboSearchParams,
+        top_authors: usize,
+    ) -> Result<SearchFacets, anyhow::Error> {
+        let query = self.build_query(params)?;
+        let facet_field = self.schema().get_field("facet").unwrap();
+        let mut collector = FacetCollector::for_field(facet_field);
+        for facet in ["/author", "/media", "/kind", "/month"] {
+            collector.add_facet(facet);
+        }
+        let counts = self.searcher()?.search(&query, &collector)?;
+
+        // facet 路径的最后一段即是取值
+        let value = |facet: &Facet| facet.to_path().last().unwrap_or(&"").to_string();
+        let mut facets = SearchFacets {
+            authors: counts
+                .top_k("/author", top_authors)
+                .into_iter()
+                .map(|(facet, count)| (value(facet), count))
+                .collect(),
+            months: counts
+                .get("/month")
+                .map(|(facet, count)| (value(facet), count))
+                .collect(),
+            ..Default::default()
+        };
+        for (facet, count) in counts.get("/media") {
+            if let Some(media_type) = value(facet).parse().ok().and_then(MediaType::from_u8) {
+                facets.media_types.push((media_type, count));
+            }
+        }
+        for (facet, count) in counts.get("/kind") {
+            match value(facet).as_str() {
+                "original" => facets.originals = count,
+                "retweet" => facets.retweets = count,
+                _ => {}
+            }
+        }
+        Ok(facets)
+    }
 
+    fn searcher(&self) -> Result<LeasedItem<Searcher>, anyhow::Error> {
         let reader = self
             .index
             .reader_builder()
             .reload_policy(ReloadPolicy::OnCommit)
             .try_into()?;
-        let searcher = reader.searcher();
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(limit))?;
+        Ok(reader.searcher())
+    }
 
-        let mut posts = vec![];
+    // 每个搜索词都须匹配，一个词在其范围内的任一字段中出现即可
+    fn text_query(
+        &self,
+        params: &WeiboSearchParams,
+        query: &str,
+    ) -> Result<Option<Box<dyn Query>>, anyhow::Error> {
         let schema = self.schema();
-        for (_score, doc_address) in top_docs {
-            let retrieved_doc = searcher.doc(doc_address)?;
-            posts.push(SearchedWeiboPost::from_doc(&schema, &retrieved_doc));
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![];
+        for term in parse_query(query) {
+            let scopes = match term.scope {
+                Some(scope) => vec![scope],
+                None => params.scopes.clone(),
+            };
+
+            let mut scope_clauses: Vec<(Occur, Box<dyn Query>)> = vec![];
+            for scope in scopes {
+                let field = schema.get_field(scope.field_name()).unwrap();
+                // 作者字段不分词，须完全匹配
+                let scope_query = match scope {
+                    SearchScope::Author | SearchScope::RetweetedAuthor => {
+                        term_query(Term::from_field_text(field, &term.text))
+                    }
+                    SearchScope::Text | SearchScope::RetweetedText => {
+                        match self.phrase_query(field, &term.text)? {
+                            Some(query) => query,
+                            None => continue,
+                        }
+                    }
+                };
+                let boost = params.boosts.get(&scope).copied().unwrap_or(1.0);
+                scope_clauses.push((Occur::Should, Box::new(BoostQuery::new(scope_query, boost))));
+            }
+            clauses.push((Occur::Must, Box::new(BooleanQuery::new(scope_clauses))));
+        }
+        if clauses.is_empty() {
+            return Ok(None);
         }
+        Ok(Some(Box::new(BooleanQuery::new(clauses))))
+    }
 
-        Ok(posts)
+    // 与 QueryParser 的处理一致: 分词后只有一个词时为 TermQuery，否则为 PhraseQuery。
+    // jieba 的分词结果会有重叠，position 是词在原文中的字符位置，索引时也是如此，所以能够匹配
+    fn phrase_query(
+        &self,
+        field: Field,
+        text: &str,
+    ) -> Result<Option<Box<dyn Query>>, anyhow::Error> {
+        let tokenizer = self.index.tokenizer_for_field(field)?;
+        let mut terms = vec![];
+        tokenizer.token_stream(text).process(&mut |token| {
+            terms.push((token.position, Term::from_field_text(field, &token.text)));
+        });
+        let query: Box<dyn Query> = match terms.len() {
+            0 => return Ok(None),
+            1 => Box::new(TermQuery::new(
+                terms.pop().unwrap().1,
+                IndexRecordOption::WithFreqs,
+            )),
+            _ => Box::new(PhraseQuery::new_with_offset(terms)),
+        };
+        Ok(Some(query))
+    }
+
+    // 高级搜索，使用 tantivy 的完整查询语法，字段名即索引中的字段名。
+    // 不带字段名的词在 params.scopes 对应的字段中搜索，多个词之间默认是「且」的关系
+    fn advanced_query(
+        &self,
+        params: &WeiboSearchParams,
+        query: &str,
+    ) -> Result<Box<dyn Query>, anyhow::Error> {
+        let schema = self.schema();
+        let default_fields = params
+            .scopes
+            .iter()
+            .map(|scope| schema.get_field(scope.field_name()).unwrap())
+            .collect();
+        let mut query_parser = QueryParser::for_index(&self.index, default_fields);
+        query_parser.set_conjunction_by_default();
+        for (scope, boost) in &params.boosts {
+            query_parser.set_field_boost(schema.get_field(scope.field_name()).unwrap(), *boost);
+        }
+        query_parser
+            .parse_query(query)
+            .map_err(|e| QuerySyntaxError::new(&schema, query, e).into())
+    }
+}
+
+fn term_query(term: Term) -> Box<dyn Query> {
+    Box::new(TermQuery::new(term, IndexRecordOption::Basic))
+}
+
+fn index_builder() -> IndexBuilder {
+    // tantivy 0.15 的 lz4 压缩实现有越界写的问题，doc store 改用 snappy
+    let settings = IndexSettings {
+        sort_by_field: None,
+        docstore_compression: Compressor::Snappy,
+    };
+    IndexBuilder::new()
+        .schema(build_schema())
+        .settings(settings)
+}
+
+fn build_schema() -> Schema {
+    let text_field_indexing = TextFieldIndexing::default()
+        .set_tokenizer("jieba")
+        .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+    let text_options = TextOptions::default()
+        .set_indexing_options(text_field_indexing)
+        .set_stored();
+
+    let mut schema_builder = Schema::builder();
+    schema_builder.add_i64_field("id", INDEXED | STORED | FAST);
+    schema_builder.add_u64_field("version", FAST);
+    schema_builder.add_i64_field("created_at", INDEXED | STORED | FAST);
+    schema_builder.add_text_field("url", STRING | STORED);
+    schema_builder.add_text_field("user", STRING | STORED);
+    schema_builder.add_text_field("text", text_options.clone());
+    schema_builder.add_u64_field("media_type", INDEXED | STORED);
+    schema_builder.add_text_field("media_url", STORED);
+    schema_builder.add_u64_field("picture_count", INDEXED);
+    schema_builder.add_u64_field("video_duration", INDEXED);
+    schema_builder.add_u64_field("visibility", INDEXED | STORED);
+    schema_builder.add_i64_field("faved_at", INDEXED | STORED);
+    schema_builder.add_u64_field("unfaved", INDEXED | STORED);
+    schema_builder.add_text_field("source", STRING);
+    schema_builder.add_text_field("retweeted_user", STRING | STORED);
+    schema_builder.add_text_field("retweeted_text", text_options);
+    schema_builder.add_facet_field("facet", INDEXED);
+    schema_builder.build()
+}
+
+// 用于 WeiboIndexer::facets 的统计，比如 /author/{作者}、/month/2022-01
+fn post_facets(post: &Post) -> Vec<Facet> {
+    let kind = if post.is_retweet() {
+        "retweet"
+    } else {
+        "original"
+    };
+    let month = post
+        .created_at
+        .with_timezone(&weibo_timezone())
+        .format("%Y-%m")
+        .to_string();
+    vec![
+        Facet::from_path(["author", &post.user.screen_name]),
+        Facet::from_path(["media", &(post.media_type() as u8).to_string()]),
+        Facet::from_path(["kind", kind]),
+        Facet::from_path(["month", &month]),
+    ]
+}
+
+// 图片微博为各图片的 url，视频微博为视频及封面的 url。转发微博取被转发微博的
+fn media_urls(post: &Post) -> Vec<&str> {
+    let media_asset = match &post.retweeted_post {
+        Some(p) => &p.media_asset,
+        None => &post.media_asset,
+    };
+    match media_asset {
+        MediaAsset::None => vec![],
+        MediaAsset::Pictures(picture_urls) => picture_urls.iter().map(|url| url.as_str()).collect(),
+        MediaAsset::Video(video) => vec![&video.url, &video.cover_picture_url],
+    }
+}
+
+// 图片数，转发微博取被转发微博的。picture_count 可能多于 url 数，见 RawPost::normalize
+fn picture_count(post: &Post) -> u32 {
+    match &post.retweeted_post {
+        Some(p) => picture_count(p),
+        None => match &post.media_asset {
+            MediaAsset::Pictures(picture_urls) => post.picture_count.max(picture_urls.len() as u32),
+            _ => 0,
+        },
     }
 }
 
+// 视频时长(秒)，不是视频微博时为 0。转发微博取被转发微博的
+fn video_duration(post: &Post) -> u32 {
+    match &post.retweeted_post {
+        Some(p) => video_duration(p),
+        None => match &post.media_asset {
+            MediaAsset::Video(video) => video.duration_secs,
+            _ => 0,
+        },
+    }
+}
+
+// 微博内容的版本，即其序列化结果的 FNV-1a 哈希。
+// 不使用 std 的 DefaultHasher，因为其结果不保证跨 Rust 版本稳定，而版本号是要持久化到索引中的。
+pub fn post_version(post: &Post) -> Result<u64, anyhow::Error> {
+    let content = serde_json::to_vec(post)?;
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in content {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    Ok(hash)
+}
+
 pub struct WeiboSearchParams {
-    pub media_type: Option<u8>,
+    // 媒体类型，转发微博为被转发微博的。以下几项同样如此
+    pub media_type: Option<MediaType>,
+    // 至少有这么多张图片
+    pub min_pictures: Option<u32>,
+    // 视频时长超过这么多秒
+    pub video_longer_than: Option<u32>,
+    // 为 true 时只搜索转发微博，为 false 时只搜索原创微博
+    pub retweet: Option<bool>,
+    // 此微博及被转发微博的可见状态，见 Post::overall_visibility
+    pub visibility: Option<PostVisibility>,
     pub user: Option<String>,
+    // 搜索词，语法见 query 模块
     pub query: Option<String>,
+    // 不带范围前缀的搜索词在这些范围中搜索
+    pub scopes: Vec<SearchScope>,
+    // 各范围的权重，未指定的为 1.0
+    pub boosts: HashMap<SearchScope, f32>,
+    // 为 true 时 query 使用 tantivy 的查询语法，见 WeiboIndexer::advanced_query
+    pub advanced: bool,
+    // 发布时间范围: [since, until)
+    pub since: Option<DateTime<FixedOffset>>,
+    pub until: Option<DateTime<FixedOffset>>,
+    // 收藏时间不早于此。收藏时间未知的微博(见 Post::faved_at)不会匹配
+    pub faved_since: Option<DateTime<FixedOffset>>,
+    // 为 true 时也搜索已取消收藏的微博
+    pub include_unfaved: bool,
+    // 只搜索从该来源抓取到的微博
+    pub source: Option<PostSource>,
+    pub sort: SortOrder,
+    // 跳过前 offset 条结果。有 cursor 时，从游标之后开始计算
+    pub offset: usize,
+    pub limit: usize,
+    // 上一页结果中的 SearchPage::next_cursor，只用于按时间排序的搜索。
+    // 与 offset 不同，翻页期间有新微博加入索引时，不会出现重复或遗漏
+    pub cursor: Option<String>,
+    // 摘要的最大长度(字符数)，为 0 时不生成摘要
+    pub snippet_len: usize,
 }
 
+impl Default for WeiboSearchParams {
+    fn default() -> WeiboSearchParams {
+        WeiboSearchParams {
+            media_type: None,
+            min_pictures: None,
+            video_longer_than: None,
+            retweet: None,
+            visibility: None,
+            user: None,
+            query: None,
+            scopes: SearchScope::ALL.to_vec(),
+            boosts: HashMap::new(),
+            advanced: false,
+            since: None,
+            until: None,
+            faved_since: None,
+            include_unfaved: false,
+            source: None,
+            sort: SortOrder::Relevance,
+            offset: 0,
+            limit: 10,
+            cursor: None,
+            snippet_len: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchPage {
+    // 匹配的微博总数
+    pub total: usize,
+    // 本页第一条微博在全部结果中的位置，从 0 开始
+    pub offset: usize,
+    pub posts: Vec<SearchedWeiboPost>,
+    // 按时间排序且还有下一页时，用于获取下一页的游标
+    pub next_cursor: Option<String>,
+}
+
+// 匹配的微博的统计，各项按数量降序，months 按月份升序
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SearchFacets {
+    pub authors: Vec<(String, u64)>,
+    pub media_types: Vec<(MediaType, u64)>,
+    pub originals: u64,
+    pub retweets: u64,
+    // 比如 ("2022-01", 12)
+    pub months: Vec<(String, u64)>,
+}
+
+// 上一页最后一条微博的发布时间及 id，格式为 {created_at}_{id}
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SearchCursor {
+    created_at: i64,
+    id: i64,
+}
+
+impl SearchCursor {
+    fn parse(s: &str) -> Result<SearchCursor, anyhow::Error> {
+        let invalid = || anyhow::format_err!("invalid cursor {:?}", s);
+        let (created_at, id) = s.split_once('_').ok_or_else(invalid)?;
+        Ok(SearchCursor {
+            created_at: created_at.parse().map_err(|_| invalid())?,
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl std::fmt::Display for SearchCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.created_at, self.id)
+    }
+}
+
+// 高级搜索中的语法错误。搜索词是用户输入的，需要与其他错误区分开，给出明确的提示
+#[derive(Debug)]
+pub struct QuerySyntaxError {
+    pub query: String,
+    pub message: String,
+}
+
+impl QuerySyntaxError {
+    fn new(schema: &Schema, query: &str, error: QueryParserError) -> QuerySyntaxError {
+        let message = match error {
+            QueryParserError::SyntaxError => {
+                "syntax error, check quotes, parentheses and field prefixes like text:xx"
+                    .to_string()
+            }
+            QueryParserError::FieldDoesNotExist(field)
+            | QueryParserError::FieldNotIndexed(field) => {
+                let fields: Vec<&str> = schema
+                    .fields()
+                    .filter(|(_, entry)| entry.is_indexed())
+                    .map(|(_, entry)| entry.name())
+                    .collect();
+                format!(
+                    "unknown field {:?}, searchable fields are: {}",
+                    field,
+                    fields.join(", ")
+                )
+            }
+            QueryParserError::ExpectedInt(e) => format!("expected an integer: {}", e),
+            QueryParserError::AllButQueryForbidden => {
+                "query must contain at least one term that is not excluded".to_string()
+            }
+            e => e.to_string(),
+        };
+        QuerySyntaxError {
+            query: query.to_string(),
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for QuerySyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid query {:?}: {}", self.query, self.message)
+    }
+}
+
+impl std::error::Error for QuerySyntaxError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+pub enum SortOrder {
+    Relevance,
+    Newest,
+    Oldest,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct SearchedWeiboPost {
+    pub id: i64,
+    pub created_at: DateTime<FixedOffset>,
+    pub visibility: PostVisibility,
+    pub faved_at: Option<DateTime<FixedOffset>>,
+    pub unfaved: bool,
     pub url: String,
     pub user: String,
     pub text: String,
+    pub media_type: MediaType,
+    pub media_urls: Vec<String>,
     pub retweeted_user: Option<String>,
     pub retweeted_text: Option<String>,
+    pub score: Option<f32>,
+    pub snippet: Option<PostSnippet>,
+}
+
+// 搜索结果的摘要，匹配的词语高亮显示
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PostSnippet {
+    // 摘要所在的字段，text 或者 retweeted_text
+    pub field: String,
+    pub fragment: String,
+    // 匹配部分在 fragment 中的字节范围
+    pub highlighted: Vec<(usize, usize)>,
+    // 以 <mark> 标记匹配部分的 HTML
+    pub html: String,
+}
+
+impl PostSnippet {
+    fn from_snippet(field: &str, snippet: &Snippet) -> PostSnippet {
+        let mut post_snippet = PostSnippet {
+            field: field.to_string(),
+            fragment: snippet.fragments().to_string(),
+            highlighted: merge_ranges(snippet.highlighted()),
+            html: String::new(),
+        };
+        post_snippet.html = post_snippet.highlight("<mark>", "</mark>", escape_html);
+        post_snippet
+    }
+
+    // 终端中以红色粗体显示匹配部分
+    pub fn to_ansi(&self) -> String {
+        self.highlight("\x1b[1;31m", "\x1b[0m", |s| s.to_string())
+    }
+
+    fn highlight<F: Fn(&str) -> String>(
+        &self,
+        start_tag: &str,
+        end_tag: &str,
+        escape: F,
+    ) -> String {
+        let mut s = String::new();
+        let mut offset = 0;
+        for &(start, end) in &self.highlighted {
+            s.push_str(&escape(&self.fragment[offset..start]));
+            s.push_str(start_tag);
+            s.push_str(&escape(&self.fragment[start..end]));
+            s.push_str(end_tag);
+            offset = end;
+        }
+        s.push_str(&escape(&self.fragment[offset..]));
+        s
+    }
+}
+
+// jieba 的分词结果会有重叠，比如「数据库」与「数据」，重叠的高亮部分需要合并
+fn merge_ranges(ranges: &[Range<usize>]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = ranges.iter().map(|r| (r.start, r.end)).collect();
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = vec![];
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
 }
 
 impl SearchedWeiboPost {
@@ -129,14 +880,35 @@ impl SearchedWeiboPost {
         use std::collections::HashMap;
 
         let mut field_values = HashMap::new();
+        let mut media_urls = vec![];
         for field_value in doc.field_values() {
             let field_name = schema.get_field_name(field_value.field()).to_string();
+            if field_name == "media_url" {
+                media_urls.extend(field_value.value().text().map(|url| url.to_string()));
+                continue;
+            }
             field_values.insert(field_name, field_value.value());
         }
 
+        let id = field_values["id"].i64_value().unwrap();
+        let created_at =
+            weibo_timezone().timestamp(field_values["created_at"].i64_value().unwrap(), 0);
+        let visibility = field_values["visibility"]
+            .u64_value()
+            .and_then(|v| PostVisibility::from_u8(v as u8))
+            .unwrap_or_default();
+        let faved_at = field_values
+            .get("faved_at")
+            .and_then(|v| v.i64_value())
+            .map(|t| weibo_timezone().timestamp(t, 0));
+        let unfaved = field_values.get("unfaved").and_then(|v| v.u64_value()) == Some(1);
         let url = field_values["url"].text().unwrap().to_string();
         let user = field_values["user"].text().unwrap().to_string();
         let text = field_values["text"].text().unwrap().to_string();
+        let media_type = field_values["media_type"]
+            .u64_value()
+            .and_then(|v| MediaType::from_u8(v as u8))
+            .unwrap_or(MediaType::Text);
 
         let retweeted_user = match field_values.get("retweeted_user") {
             None => None,
@@ -152,11 +924,619 @@ impl SearchedWeiboPost {
         };
 
         SearchedWeiboPost {
+            id,
+            created_at,
+            visibility,
+            faved_at,
+            unfaved,
             url,
             user,
             text,
+            media_type,
+            media_urls,
             retweeted_user,
             retweeted_text,
+            score: None,
+            snippet: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::load_post;
+
+    #[test]
+    fn test_incremental_indexing() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let text_post = load_post(include_str!("../test_data/text.json"));
+        let video_post = load_post(include_str!("../test_data/video.json"));
+        indexer.index_weibo_posts(&[text_post.clone(), video_post.clone()])?;
+
+        let versions = indexer.indexed_post_versions()?;
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[&text_post.id], post_version(&text_post)?);
+
+        // 重新索引修改过的微博，不会产生重复文档
+        let mut changed_post = text_post.clone();
+        changed_post.text_raw.push_str("（已编辑）");
+        indexer.index_weibo_posts(&[changed_post.clone()])?;
+        let versions = indexer.indexed_post_versions()?;
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[&text_post.id], post_version(&changed_post)?);
+        assert_ne!(versions[&text_post.id], post_version(&text_post)?);
+
+        indexer.delete_posts(&[video_post.id])?;
+        let versions = indexer.indexed_post_versions()?;
+        assert_eq!(versions.len(), 1);
+        assert!(!versions.contains_key(&video_post.id));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_by_date() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let posts = vec![
+            load_post(include_str!("../test_data/text.json")),
+            load_post(include_str!("../test_data/video.json")),
+            load_post(include_str!("../test_data/picture.json")),
+        ];
+        indexer.index_weibo_posts(&posts)?;
+
+        let mut params = WeiboSearchParams {
+            sort: SortOrder::Newest,
+            ..Default::default()
+        };
+        let mut expected: Vec<(i64, DateTime<FixedOffset>)> =
+            posts.iter().map(|p| (p.id, p.created_at)).collect();
+        expected.sort_by_key(|(_, created_at)| std::cmp::Reverse(*created_at));
+
+        let found = indexer.search(&params)?.posts;
+        let found: Vec<(i64, DateTime<FixedOffset>)> =
+            found.iter().map(|p| (p.id, p.created_at)).collect();
+        assert_eq!(found, expected);
+
+        params.sort = SortOrder::Oldest;
+        let found = indexer.search(&params)?.posts;
+        assert_eq!(found[0].id, expected[2].0);
+
+        // until 不包含边界
+        params.until = Some(expected[0].1);
+        let found = indexer.search(&params)?.posts;
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|p| p.id != expected[0].0));
+
+        params.since = Some(expected[1].1);
+        let found = indexer.search(&params)?.posts;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, expected[1].0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_by_visibility() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let text_post = load_post(include_str!("../test_data/text.json"));
+        let deleted_post = load_post(include_str!("../test_data/retweet_deleted.json"));
+        indexer.index_weibo_posts(&[text_post.clone(), deleted_post.clone()])?;
+
+        let mut params = WeiboSearchParams {
+            visibility: Some(PostVisibility::Deleted),
+            sort: SortOrder::Newest,
+            ..Default::default()
+        };
+        let found = indexer.search(&params)?.posts;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, deleted_post.id);
+        assert_eq!(found[0].visibility, PostVisibility::Deleted);
+
+        params.visibility = Some(PostVisibility::Visible);
+        let found = indexer.search(&params)?.posts;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, text_post.id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_by_fav_status() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let day = |d: u32| weibo_timezone().ymd(2022, 2, d).and_hms(0, 0, 0);
+        let mut text_post = load_post(include_str!("../test_data/text.json"));
+        text_post.faved_at = Some(day(1));
+        let mut video_post = load_post(include_str!("../test_data/video.json"));
+        video_post.faved_at = Some(day(10));
+        let mut picture_post = load_post(include_str!("../test_data/picture.json"));
+        picture_post.faved_at = Some(day(20));
+        picture_post.unfaved_at = Some(day(21));
+        // 收藏时间未知
+        let retweet = load_post(include_str!("../test_data/text_retweet.json"));
+        indexer.index_weibo_posts(&[
+            text_post.clone(),
+            video_post.clone(),
+            picture_post.clone(),
+            retweet.clone(),
+        ])?;
+
+        let search = |params: WeiboSearchParams| -> Vec<i64> {
+            let params = WeiboSearchParams {
+                sort: SortOrder::Oldest,
+                ..params
+            };
+            let page = indexer.search(&params).unwrap();
+            page.posts.iter().map(|p| p.id).collect()
+        };
+
+        // 默认不包括已取消收藏的微博
+        let found = search(WeiboSearchParams::default());
+        assert_eq!(found.len(), 3);
+        assert!(!found.contains(&picture_post.id));
+
+        let found = indexer.search(&WeiboSearchParams {
+            include_unfaved: true,
+            user: Some(picture_post.user.screen_name.clone()),
+            ..Default::default()
+        })?;
+        assert!(found
+            .posts
+            .iter()
+            .any(|p| p.id == picture_post.id && p.unfaved));
+
+        let mut found = search(WeiboSearchParams {
+            faved_since: Some(day(5)),
+            include_unfaved: true,
+            ..Default::default()
+        });
+        found.sort_unstable();
+        let mut expected = vec![video_post.id, picture_post.id];
+        expected.sort_unstable();
+        assert_eq!(found, expected);
+
+        let found = indexer.search(&WeiboSearchParams {
+            faved_since: Some(day(5)),
+            ..Default::default()
+        })?;
+        assert_eq!(found.posts.len(), 1);
+        assert_eq!(found.posts[0].id, video_post.id);
+        assert_eq!(found.posts[0].faved_at, Some(day(10)));
+        assert!(!found.posts[0].unfaved);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_by_source() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let mut text_post = load_post(include_str!("../test_data/text.json"));
+        text_post.sources = vec![PostSource::Favorites, PostSource::Likes];
+        let mut video_post = load_post(include_str!("../test_data/video.json"));
+        video_post.sources = vec![PostSource::User(1773116334)];
+        indexer.index_weibo_posts(&[text_post.clone(), video_post.clone()])?;
+
+        let search = |source: PostSource| -> Vec<i64> {
+            let params = WeiboSearchParams {
+                source: Some(source),
+                ..Default::default()
+            };
+            let page = indexer.search(&params).unwrap();
+            page.posts.iter().map(|p| p.id).collect()
+        };
+        assert_eq!(search(PostSource::Likes), vec![text_post.id]);
+        assert_eq!(search(PostSource::User(1773116334)), vec![video_post.id]);
+        assert!(search(PostSource::Mine).is_empty());
+        assert_eq!(
+            indexer.search(&WeiboSearchParams::default())?.posts.len(),
+            2
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_searched_post_fields() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let picture_post = load_post(include_str!("../test_data/picture_retweet.json"));
+        indexer.index_weibo_posts(std::slice::from_ref(&picture_post))?;
+
+        let mut params = WeiboSearchParams {
+            sort: SortOrder::Newest,
+            ..Default::default()
+        };
+        let found = indexer.search(&params)?.posts;
+        assert_eq!(found[0].media_type, MediaType::Picture);
+        assert_eq!(found[0].media_urls.len(), 18);
+        assert!(found[0].score.is_none());
+
+        let json = serde_json::to_value(&found[0])?;
+        assert_eq!(json["id"], picture_post.id);
+        assert_eq!(json["url"], picture_post.url());
+
+        params.sort = SortOrder::Relevance;
+        let found = indexer.search(&params)?.posts;
+        assert!(found[0].score.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_snippet() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let text_post = load_post(include_str!("../test_data/text.json"));
+        indexer.index_weibo_posts(std::slice::from_ref(&text_post))?;
+
+        let params = WeiboSearchParams {
+            query: Some("数据库".to_string()),
+            snippet_len: 20,
+            ..Default::default()
+        };
+        let found = indexer.search(&params)?.posts;
+        let snippet = found[0].snippet.as_ref().unwrap();
+        assert_eq!(snippet.field, "text");
+        assert!(snippet.fragment.chars().count() <= 20);
+        assert!(!snippet.highlighted.is_empty());
+        for &(start, end) in &snippet.highlighted {
+            assert_eq!(&snippet.fragment[start..end], "数据库");
+        }
+        assert!(snippet.html.contains("<mark>数据库</mark>"));
+        assert!(snippet.to_ansi().contains("\x1b[1;31m数据库\x1b[0m"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_scopes() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let text_post = load_post(include_str!("../test_data/text.json"));
+        let retweet_post = load_post(include_str!("../test_data/text_retweet.json"));
+        indexer.index_weibo_posts(&[text_post.clone(), retweet_post.clone()])?;
+
+        let search = |query: &str, scopes: &[SearchScope]| -> Vec<i64> {
+            let params = WeiboSearchParams {
+                query: Some(query.to_string()),
+                scopes: scopes.to_vec(),
+                ..Default::default()
+            };
+            let found = indexer.search(&params).unwrap().posts;
+            found.iter().map(|p| p.id).collect()
+        };
+        let all = &SearchScope::ALL;
+
+        // 默认也搜索被转发微博的正文及作者
+        assert_eq!(search("Moxie", all), vec![retweet_post.id]);
+        assert_eq!(search("木遥", all), vec![retweet_post.id]);
+        assert_eq!(search("zhh-4096", all), vec![text_post.id]);
+        assert!(search("Moxie", &[SearchScope::Text]).is_empty());
+
+        // 范围前缀
+        assert!(search("text:Moxie", all).is_empty());
+        assert_eq!(search("rt:Moxie", all), vec![retweet_post.id]);
+        assert_eq!(search("rtauthor:木遥", all), vec![retweet_post.id]);
+        assert!(search("author:木遥", all).is_empty());
+        assert_eq!(search("author:郭宇 rt:Moxie", all), vec![retweet_post.id]);
+        assert!(search("author:郭宇 数据库", all).is_empty());
+
+        // 多个词都须匹配，引号括起的词中的空格不会拆分
+        assert_eq!(search("数据库 GraalVM", all), vec![text_post.id]);
+        assert!(search("数据库 Moxie", all).is_empty());
+        assert_eq!(search("\"GraalVM 为工具\"", all), vec![text_post.id]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_boosts() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let retweet_post = load_post(include_str!("../test_data/text_retweet.json"));
+        indexer.index_weibo_posts(&[retweet_post])?;
+
+        let mut params = WeiboSearchParams {
+            query: Some("Moxie".to_string()),
+            ..Default::default()
+        };
+        let score = indexer.search(&params)?.posts[0].score.unwrap();
+        params.boosts.insert(SearchScope::RetweetedText, 2.0);
+        let boosted_score = indexer.search(&params)?.posts[0].score.unwrap();
+        assert!((boosted_score - score * 2.0).abs() < 1e-4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_filters() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let text_post = load_post(include_str!("../test_data/text.json"));
+        let mut retweet_post = load_post(include_str!("../test_data/text_retweet.json"));
+        // 昵称中的空格和冒号不应被当作查询语法
+        retweet_post.user.screen_name = "郭 宇:(AR)".to_string();
+        indexer.index_weibo_posts(&[text_post.clone(), retweet_post.clone()])?;
+
+        let mut params = WeiboSearchParams {
+            user: Some("郭 宇:(AR)".to_string()),
+            ..Default::default()
+        };
+        let found = indexer.search(&params)?.posts;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, retweet_post.id);
+
+        params.query = Some("AR NFT".to_string());
+        let found = indexer.search(&params)?.posts;
+        assert_eq!(found.len(), 1);
+        // 搜索词中的标点作为普通字符，不会出错
+        params.query = Some("AR: (NFT) \"OpenSea".to_string());
+        assert!(indexer.search(&params).is_ok());
+
+        params.user = None;
+        params.query = None;
+        params.media_type = Some(MediaType::Text);
+        assert_eq!(indexer.search(&params)?.posts.len(), 2);
+        params.media_type = Some(MediaType::Video);
+        assert!(indexer.search(&params)?.posts.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_advanced_search() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let text_post = load_post(include_str!("../test_data/text.json"));
+        let retweet_post = load_post(include_str!("../test_data/text_retweet.json"));
+        indexer.index_weibo_posts(&[text_post.clone(), retweet_post.clone()])?;
+
+        let search = |query: &str| -> Result<Vec<i64>, anyhow::Error> {
+            let params = WeiboSearchParams {
+                query: Some(query.to_string()),
+                advanced: true,
+                sort: SortOrder::Newest,
+                ..Default::default()
+            };
+            let found = indexer.search(&params)?.posts;
+            Ok(found.iter().map(|p| p.id).collect())
+        };
+
+        assert_eq!(search("Moxie")?, vec![retweet_post.id]);
+        assert_eq!(search("数据库 OR retweeted_user:木遥")?.len(), 2);
+        assert_eq!(search("(数据库 OR Moxie) -user:郭宇")?, vec![text_post.id]);
+        assert!(search("text:Moxie")?.is_empty());
+
+        let e = search("text:(Moxie").unwrap_err();
+        assert!(e.is::<QuerySyntaxError>());
+        assert!(e.to_string().contains("syntax error"));
+        let e = search("rt:Moxie").unwrap_err();
+        assert!(e.to_string().contains("unknown field \"rt\""));
+        assert!(e.to_string().contains("retweeted_text"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_paging() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let posts = [
+            load_post(include_str!("../test_data/text.json")),
+            load_post(include_str!("../test_data/video.json")),
+            load_post(include_str!("../test_data/picture.json")),
+            load_post(include_str!("../test_data/text_retweet.json")),
+            load_post(include_str!("../test_data/video_retweet.json")),
+        ];
+        indexer.index_weibo_posts(&posts[..4])?;
+
+        let mut params = WeiboSearchParams {
+            sort: SortOrder::Newest,
+            limit: 100,
+            ..Default::default()
+        };
+        let all: Vec<i64> = indexer
+            .search(&params)?
+            .posts
+            .iter()
+            .map(|p| p.id)
+            .collect();
+        assert_eq!(all.len(), 4);
+
+        params.limit = 3;
+        params.offset = 2;
+        let page = indexer.search(&params)?;
+        assert_eq!(page.total, 4);
+        assert_eq!(page.offset, 2);
+        assert_eq!(page.posts.len(), 2);
+        assert_eq!(page.posts[0].id, all[2]);
+        assert!(page.next_cursor.is_none());
+
+        // 翻页期间加入的新微博，不影响游标之后的结果
+        params.limit = 2;
+        params.offset = 0;
+        let page = indexer.search(&params)?;
+        assert_eq!(page.posts[1].id, all[1]);
+        indexer.index_weibo_posts(&posts[4..])?;
+        params.cursor = page.next_cursor;
+        let page = indexer.search(&params)?;
+        assert_eq!(page.total, 5);
+        let ids: Vec<i64> = page.posts.iter().map(|p| p.id).collect();
+        assert_eq!(ids, all[2..]);
+        assert!(page.next_cursor.is_none());
+
+        params.sort = SortOrder::Oldest;
+        params.cursor = None;
+        let page = indexer.search(&params)?;
+        params.cursor = page.next_cursor;
+        let page = indexer.search(&params)?;
+        assert_eq!(page.offset, 2);
+
+        // 同一秒发布的微博，按 id 排序，逐条翻页时不会重复或遗漏
+        let same_second: Vec<Post> = (1..=3)
+            .map(|i| {
+                let mut post = posts[0].clone();
+                post.id += i;
+                post
+            })
+            .collect();
+        indexer.index_weibo_posts(&same_second)?;
+        params.sort = SortOrder::Newest;
+        params.limit = 1;
+        params.cursor = None;
+        let mut ids = vec![];
+        loop {
+            let page = indexer.search(&params)?;
+            assert_eq!(page.offset, ids.len());
+            ids.extend(page.posts.iter().map(|p| p.id));
+            match page.next_cursor {
+                Some(cursor) => params.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(ids.len(), 8);
+        assert_eq!(
+            &ids[1..5],
+            &[
+                posts[0].id + 3,
+                posts[0].id + 2,
+                posts[0].id + 1,
+                posts[0].id
+            ]
+        );
+
+        params.sort = SortOrder::Relevance;
+        assert!(indexer.search(&params).is_err());
+        params.sort = SortOrder::Newest;
+        params.cursor = Some("yesterday".to_string());
+        assert!(indexer.search(&params).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_facets() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let text_post = load_post(include_str!("../test_data/text.json"));
+        let video_post = load_post(include_str!("../test_data/video.json"));
+        let picture_post = load_post(include_str!("../test_data/picture.json"));
+        let retweet_post = load_post(include_str!("../test_data/text_retweet.json"));
+        let mut another_text_post = text_post.clone();
+        another_text_post.id += 1;
+        another_text_post.created_at = weibo_timezone().ymd(2021, 12, 31).and_hms(23, 0, 0);
+        indexer.index_weibo_posts(&[
+            text_post.clone(),
+            video_post.clone(),
+            picture_post.clone(),
+            retweet_post.clone(),
+            another_text_post,
+        ])?;
+
+        let mut params = WeiboSearchParams::default();
+        let facets = indexer.facets(&params, 1)?;
+        assert_eq!(
+            facets.authors,
+            vec![(text_post.user.screen_name.clone(), 2)]
+        );
+        assert_eq!(
+            facets.media_types,
+            vec![
+                (MediaType::Text, 3),
+                (MediaType::Picture, 1),
+                (MediaType::Video, 1)
+            ]
+        );
+        assert_eq!((facets.originals, facets.retweets), (4, 1));
+        assert_eq!(
+            facets.months,
+            vec![("2021-12".to_string(), 1), ("2022-01".to_string(), 4)]
+        );
+
+        // 只统计匹配的微博
+        params.query = Some("Moxie".to_string());
+        let facets = indexer.facets(&params, 10)?;
+        assert_eq!(facets.authors, vec![(retweet_post.user.screen_name, 1)]);
+        assert_eq!((facets.originals, facets.retweets), (0, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_by_media() -> Result<(), anyhow::Error> {
+        let indexer = WeiboIndexer::in_ram();
+        let text_post = load_post(include_str!("../test_data/text.json"));
+        let picture_post = load_post(include_str!("../test_data/picture.json"));
+        let picture_retweet = load_post(include_str!("../test_data/picture_retweet.json"));
+        let video_post = load_post(include_str!("../test_data/video.json"));
+        let video_retweet = load_post(include_str!("../test_data/video_retweet.json"));
+        indexer.index_weibo_posts(&[
+            text_post.clone(),
+            picture_post.clone(),
+            picture_retweet.clone(),
+            video_post.clone(),
+            video_retweet.clone(),
+        ])?;
+
+        let search = |params: WeiboSearchParams| -> Vec<i64> {
+            let params = WeiboSearchParams {
+                sort: SortOrder::Newest,
+                ..params
+            };
+            let mut ids: Vec<i64> = indexer
+                .search(&params)
+                .unwrap()
+                .posts
+                .iter()
+                .map(|p| p.id)
+                .collect();
+            ids.sort_unstable();
+            ids
+        };
+        let sorted = |mut ids: Vec<i64>| {
+            ids.sort_unstable();
+            ids
+        };
+
+        assert_eq!(
+            search(WeiboSearchParams {
+                media_type: Some(MediaType::Picture),
+                ..Default::default()
+            }),
+            sorted(vec![picture_post.id, picture_retweet.id])
+        );
+        assert_eq!(
+            search(WeiboSearchParams {
+                min_pictures: Some(18),
+                ..Default::default()
+            })
+            .len(),
+            2
+        );
+        assert!(search(WeiboSearchParams {
+            min_pictures: Some(19),
+            ..Default::default()
+        })
+        .is_empty());
+
+        // 视频时长分别为 69 秒和 63 秒
+        assert_eq!(
+            search(WeiboSearchParams {
+                video_longer_than: Some(63),
+                ..Default::default()
+            }),
+            vec![video_post.id]
+        );
+        assert_eq!(
+            search(WeiboSearchParams {
+                video_longer_than: Some(60),
+                ..Default::default()
+            })
+            .len(),
+            2
+        );
+
+        assert_eq!(
+            search(WeiboSearchParams {
+                retweet: Some(true),
+                ..Default::default()
+            }),
+            sorted(vec![picture_retweet.id, video_retweet.id])
+        );
+        assert_eq!(
+            search(WeiboSearchParams {
+                retweet: Some(false),
+                ..Default::default()
+            }),
+            sorted(vec![text_post.id, picture_post.id, video_post.id])
+        );
+        assert_eq!(
+            search(WeiboSearchParams {
+                retweet: Some(true),
+                media_type: Some(MediaType::Video),
+                ..Default::default()
+            }),
+            vec![video_retweet.id]
+        );
+        Ok(())
+    }
+}
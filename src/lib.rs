@@ -1,5 +1,12 @@
 pub mod chromedriver;
 pub mod commands;
+pub mod crawler;
 pub mod index;
+pub mod media;
+pub mod query;
+pub mod render;
 pub mod storage;
 pub mod weibo;
+
+#[cfg(test)]
+mod test_util;
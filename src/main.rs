@@ -18,8 +18,13 @@ struct Config {
 #[derive(Debug, clap::Parser)]
 enum Command {
     Crawl(commands::crawl::Config),
+    Export(commands::export::Config),
+    Import(commands::import::Config),
     Index(commands::index::Config),
+    Media(commands::media::Config),
+    Renormalize(commands::renormalize::Config),
     Search(commands::search::Config),
+    Serve(commands::serve::Config),
     Tombstone(commands::tombstone::Config),
     Settings(commands::settings::Config),
 }
@@ -31,8 +36,13 @@ async fn main() -> Result<(), anyhow::Error> {
 
     match global_config.command {
         Command::Crawl(config) => commands::crawl::command(config).await?,
+        Command::Export(config) => commands::export::command(config).await?,
+        Command::Import(config) => commands::import::command(config).await?,
         Command::Index(config) => commands::index::command(config).await?,
+        Command::Media(config) => commands::media::command(config).await?,
+        Command::Renormalize(config) => commands::renormalize::command(config).await?,
         Command::Search(config) => commands::search::command(config).await?,
+        Command::Serve(config) => commands::serve::command(config).await?,
         Command::Tombstone(config) => commands::tombstone::command(config).await?,
         Command::Settings(config) => commands::settings::command(config).await?,
     }
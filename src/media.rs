@@ -0,0 +1,108 @@
+use crate::crawler::{BROWSER_USER_AGENT, WEIBO_URL};
+use reqwest::header::{HeaderMap, HeaderValue, REFERER, USER_AGENT};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// 图片的本地存档。文件以内容的 sha256 命名，按前两位分到子目录中，
+// 比如 media/3f/3fa2...9c.jpg。同样内容的图片只保存一份。
+pub struct MediaArchiver {
+    client: reqwest::Client,
+    media_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchivedMedia {
+    pub sha256: String,
+    // 相对于 media 目录的路径
+    pub path: String,
+}
+
+impl MediaArchiver {
+    pub fn new<P: AsRef<Path>>(media_dir: P) -> Result<MediaArchiver, anyhow::Error> {
+        // 新浪图床会检查 Referer
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static(BROWSER_USER_AGENT));
+        headers.insert(REFERER, HeaderValue::from_str(&format!("{}/", WEIBO_URL))?);
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(60))
+            .build()?;
+
+        Ok(MediaArchiver {
+            client,
+            media_dir: media_dir.as_ref().to_path_buf(),
+        })
+    }
+
+    pub async fn archive(&self, url: &str) -> Result<ArchivedMedia, anyhow::Error> {
+        let bytes = self.download(url).await?;
+        self.store(url, &bytes)
+    }
+
+    pub fn full_path(&self, path: &str) -> PathBuf {
+        self.media_dir.join(path)
+    }
+
+    async fn download(&self, url: &str) -> Result<Vec<u8>, anyhow::Error> {
+        let resp = self.client.get(url).send().await?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(anyhow::format_err!("HTTP status {}", status));
+        }
+        let bytes = resp.bytes().await?;
+        if bytes.is_empty() {
+            return Err(anyhow::format_err!("empty response"));
+        }
+        Ok(bytes.to_vec())
+    }
+
+    fn store(&self, url: &str, bytes: &[u8]) -> Result<ArchivedMedia, anyhow::Error> {
+        let sha256: String = Sha256::digest(bytes)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let path = format!("{}/{}.{}", &sha256[..2], sha256, file_extension(url));
+
+        let full_path = self.full_path(&path);
+        if !full_path.exists() {
+            fs::create_dir_all(full_path.parent().unwrap())?;
+            // 先写入临时文件再改名，中断时不会留下不完整的文件
+            let tmp_path = full_path.with_extension("tmp");
+            fs::write(&tmp_path, bytes)?;
+            fs::rename(&tmp_path, &full_path)?;
+        }
+        Ok(ArchivedMedia { sha256, path })
+    }
+}
+
+fn file_extension(url: &str) -> &str {
+    let path = url.split(&['?', '#'][..]).next().unwrap_or_default();
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+    match file_name.rsplit_once('.') {
+        Some((_, ext))
+            if !ext.is_empty()
+                && ext.len() <= 5
+                && ext.chars().all(|c| c.is_ascii_alphanumeric()) =>
+        {
+            ext
+        }
+        _ => "bin",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_extension() {
+        assert_eq!(
+            file_extension("https://wx1.sinaimg.cn/orj360/69d0a7e5ly1gy4glx9ryfj20u01hcqbe.jpg"),
+            "jpg"
+        );
+        assert_eq!(file_extension("https://example.com/a.gif?x=1"), "gif");
+        assert_eq!(file_extension("https://example.com/picture"), "bin");
+    }
+}
@@ -0,0 +1,180 @@
+// 搜索词的解析。
+// 搜索词以空格分隔，多个词之间是「且」的关系，双引号括起的部分作为一个词。
+// 每个词可以带上范围前缀，比如 rt:web3、author:"某 人"，只在该范围中搜索；
+// 不带前缀的词在 WeiboSearchParams::scopes 指定的范围中搜索。
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ArgEnum)]
+pub enum SearchScope {
+    /// 微博正文
+    Text,
+    /// 被转发微博的正文
+    #[clap(name = "rt")]
+    RetweetedText,
+    /// 作者
+    Author,
+    /// 被转发微博的作者
+    #[clap(name = "rtauthor")]
+    RetweetedAuthor,
+}
+
+impl SearchScope {
+    pub const ALL: [SearchScope; 4] = [
+        SearchScope::Text,
+        SearchScope::RetweetedText,
+        SearchScope::Author,
+        SearchScope::RetweetedAuthor,
+    ];
+
+    // 搜索词中的前缀，同时也是命令行参数中的名字
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            SearchScope::Text => "text",
+            SearchScope::RetweetedText => "rt",
+            SearchScope::Author => "author",
+            SearchScope::RetweetedAuthor => "rtauthor",
+        }
+    }
+
+    pub fn from_prefix(prefix: &str) -> Option<SearchScope> {
+        SearchScope::ALL
+            .iter()
+            .copied()
+            .find(|scope| scope.prefix() == prefix)
+    }
+
+    // 对应的索引字段
+    pub fn field_name(&self) -> &'static str {
+        match self {
+            SearchScope::Text => "text",
+            SearchScope::RetweetedText => "retweeted_text",
+            SearchScope::Author => "user",
+            SearchScope::RetweetedAuthor => "retweeted_user",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryTerm {
+    // 为 None 时在默认范围中搜索
+    pub scope: Option<SearchScope>,
+    pub text: String,
+}
+
+pub fn parse_query(query: &str) -> Vec<QueryTerm> {
+    let mut terms = vec![];
+    let mut chars = query.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        // 先读到空格或者引号为止，再判断是否是范围前缀
+        let mut word = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '"') {
+            word.push(c);
+        }
+        let scope = match word.strip_suffix(':').and_then(SearchScope::from_prefix) {
+            Some(scope) => {
+                word.clear();
+                Some(scope)
+            }
+            None => match word.split_once(':') {
+                Some((prefix, rest)) if SearchScope::from_prefix(prefix).is_some() => {
+                    let scope = SearchScope::from_prefix(prefix);
+                    word = rest.to_string();
+                    scope
+                }
+                _ => None,
+            },
+        };
+
+        if word.is_empty() && chars.next_if_eq(&'"').is_some() {
+            // 引号未闭合时，取到结尾为止
+            while let Some(c) = chars.next_if(|c| *c != '"') {
+                word.push(c);
+            }
+            chars.next();
+        } else {
+            // 词中间的引号作为普通字符
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+
+        let text = word.trim();
+        if !text.is_empty() {
+            terms.push(QueryTerm {
+                scope,
+                text: text.to_string(),
+            });
+        }
+    }
+    terms
+}
+
+// 命令行中的 --boost rt=0.5
+pub fn parse_boost(s: &str) -> Result<(SearchScope, f32), anyhow::Error> {
+    let (prefix, boost) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::format_err!("invalid boost {:?}, expected SCOPE=WEIGHT", s))?;
+    let scope = SearchScope::from_prefix(prefix)
+        .ok_or_else(|| anyhow::format_err!("unknown search scope {:?}", prefix))?;
+    let boost: f32 = boost
+        .parse()
+        .map_err(|_| anyhow::format_err!("invalid boost weight {:?}", boost))?;
+    if boost.is_nan() || boost <= 0.0 {
+        return Err(anyhow::format_err!("boost weight must be positive"));
+    }
+    Ok((scope, boost))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(scope: Option<SearchScope>, text: &str) -> QueryTerm {
+        QueryTerm {
+            scope,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            parse_query(" 数据库  rt:web3 author:\"某 人\" rtauthor: 木遥 "),
+            vec![
+                term(None, "数据库"),
+                term(Some(SearchScope::RetweetedText), "web3"),
+                term(Some(SearchScope::Author), "某 人"),
+                term(None, "木遥"),
+            ]
+        );
+        // 不认识的前缀，以及没有闭合的引号
+        assert_eq!(
+            parse_query("http://t.cn/A6JymyR7 \"JPEG 压缩"),
+            vec![term(None, "http://t.cn/A6JymyR7"), term(None, "JPEG 压缩"),]
+        );
+        assert_eq!(
+            parse_query("text:\"a\"b c\"d"),
+            vec![
+                term(Some(SearchScope::Text), "a"),
+                term(None, "b"),
+                term(None, "c\"d")
+            ]
+        );
+        assert!(parse_query("  \"\" rt:").is_empty());
+    }
+
+    #[test]
+    fn test_parse_boost() {
+        assert_eq!(
+            parse_boost("rt=0.5").unwrap(),
+            (SearchScope::RetweetedText, 0.5)
+        );
+        assert!(parse_boost("rt").is_err());
+        assert!(parse_boost("foo=1").is_err());
+        assert!(parse_boost("text=-1").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::weibo::post::{MediaAsset, Post};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// weise serve 的搜索页面和 weise export 导出的 HTML 共用的渲染
+
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub const PAGE_STYLE: &str = r#"
+body { max-width: 720px; margin: 0 auto; padding: 16px; font-family: sans-serif; color: #333; }
+form { display: flex; flex-wrap: wrap; gap: 8px; margin-bottom: 24px; }
+form input[name=q] { flex: 1 1 100%; font-size: 16px; padding: 6px; }
+.post { border-bottom: 1px solid #eee; padding: 12px 0; }
+.post header { font-size: 14px; color: #888; margin-bottom: 6px; }
+.post header a { color: #eb7350; text-decoration: none; }
+.label { background: #f5d5cc; border-radius: 3px; padding: 0 4px; margin-left: 6px; }
+.text { white-space: pre-wrap; line-height: 1.6; }
+.retweet { background: #f7f7f7; margin: 8px 0 0; padding: 8px 12px; }
+.pictures img, .video img { max-width: 160px; max-height: 160px; margin: 4px 4px 0 0; object-fit: cover; }
+.error { color: #c00; }
+.snippet { margin: 0 0 6px; color: #666; }
+.snippet mark { background: #ffe58f; }
+.pager { display: flex; align-items: center; gap: 12px; padding: 16px 0; color: #888; }
+.pager form { margin: 0; }
+"#;
+
+// 微博及被转发微博中的图片和视频封面
+pub fn media_urls(post: &Post) -> Vec<String> {
+    let mut urls = match &post.media_asset {
+        MediaAsset::None => vec![],
+        MediaAsset::Pictures(picture_urls) => picture_urls.clone(),
+        MediaAsset::Video(video) => vec![video.cover_picture_url.clone()],
+    };
+    if let Some(retweeted_post) = &post.retweeted_post {
+        urls.extend(media_urls(retweeted_post));
+    }
+    urls
+}
+
+// archived_media 为原 url 到本地 url 的映射
+pub fn render_post(html: &mut String, post: &Post, archived_media: &HashMap<String, String>) {
+    write!(
+        html,
+        r#"<header><a href="{}">@{}</a> · <a href="{}">{}</a>"#,
+        escape_html(&post.user.profile_url()),
+        escape_html(&post.user.screen_name),
+        escape_html(&post.url()),
+        post.created_at.format("%Y-%m-%d %H:%M"),
+    )
+    .unwrap();
+    if !post.is_visible() {
+        write!(
+            html,
+            r#"<span class="label">{}</span>"#,
+            post.visibility.label()
+        )
+        .unwrap();
+    }
+    if post.unfaved_at.is_some() {
+        html.push_str(r#"<span class="label">已取消收藏</span>"#);
+    }
+    write!(
+        html,
+        r#"</header><div class="text">{}</div>"#,
+        escape_html(post.full_text())
+    )
+    .unwrap();
+
+    // 新浪图床会拒绝带有其他站点 Referer 的请求
+    let local_or_remote = |url: &str| match archived_media.get(url) {
+        Some(local_url) => escape_html(local_url),
+        None => escape_html(url),
+    };
+    match &post.media_asset {
+        MediaAsset::None => {}
+        MediaAsset::Pictures(picture_urls) => {
+            html.push_str(r#"<div class="pictures">"#);
+            for url in picture_urls {
+                let src = local_or_remote(url);
+                write!(
+                    html,
+                    r#"<a href="{}"><img src="{}" loading="lazy" referrerpolicy="no-referrer"></a>"#,
+                    src, src
+                )
+                .unwrap();
+            }
+            html.push_str("</div>");
+        }
+        MediaAsset::Video(video) => {
+            write!(
+                html,
+                r#"<div class="video"><a href="{}"><img src="{}" loading="lazy" referrerpolicy="no-referrer"><br>视频 {}:{:02}</a></div>"#,
+                escape_html(&video.url),
+                local_or_remote(&video.cover_picture_url),
+                video.duration_secs / 60,
+                video.duration_secs % 60,
+            )
+            .unwrap();
+        }
+    }
+
+    if let Some(retweeted_post) = &post.retweeted_post {
+        html.push_str(r#"<blockquote class="retweet">"#);
+        render_post(html, retweeted_post, archived_media);
+        html.push_str("</blockquote>");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+    }
+}
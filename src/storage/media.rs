@@ -0,0 +1,107 @@
+use crate::storage::Storage;
+use rusqlite::{named_params, Row};
+
+// media 表记录微博中图片(包括视频封面)的 URL 与本地存档文件的对应关系。
+// 同样内容的图片只保存一份，文件名即是内容的 sha256。
+
+pub struct MediaStorage<'a> {
+    pub(super) storage: &'a Storage,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRecord {
+    pub url: String,
+    pub sha256: Option<String>,
+    // 相对于 media 目录的路径
+    pub path: Option<String>,
+    pub error: Option<String>,
+}
+
+impl MediaRecord {
+    fn from_row(row: &Row) -> Result<MediaRecord, rusqlite::Error> {
+        Ok(MediaRecord {
+            url: row.get(0)?,
+            sha256: row.get(1)?,
+            path: row.get(2)?,
+            error: row.get(3)?,
+        })
+    }
+}
+
+impl<'a> MediaStorage<'a> {
+    // 所有微博(包括被转发的微博)中引用的图片和视频封面
+    pub fn referenced_urls(&self) -> Result<Vec<String>, anyhow::Error> {
+        let sql = r#"
+            select url from picture
+            union
+            select cover_picture_url from video where cover_picture_url != ''
+            order by 1
+        "#;
+        let mut stmt = self.storage.conn.prepare(sql)?;
+        let mut rows = stmt.query([])?;
+
+        let mut urls = vec![];
+        while let Some(row) = rows.next()? {
+            urls.push(row.get(0)?);
+        }
+        Ok(urls)
+    }
+
+    pub fn get(&self, url: &str) -> Result<Option<MediaRecord>, anyhow::Error> {
+        let sql = "select url, sha256, path, error from media where url = :url";
+        let mut stmt = self.storage.conn.prepare_cached(sql)?;
+        let mut rows = stmt.query(named_params! {":url": url})?;
+        match rows.next()? {
+            Some(row) => Ok(Some(MediaRecord::from_row(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    // 已存档的图片在 media 目录中的相对路径
+    pub fn archived_path(&self, url: &str) -> Result<Option<String>, anyhow::Error> {
+        Ok(self.get(url)?.and_then(|record| record.path))
+    }
+
+    pub fn set_archived(&self, url: &str, sha256: &str, path: &str) -> Result<(), anyhow::Error> {
+        let sql = r#"
+            insert or replace into media (url, sha256, path, error)
+            values (:url, :sha256, :path, null)
+        "#;
+        self.storage.conn.execute(
+            sql,
+            named_params! {
+                ":url": url,
+                ":sha256": sha256,
+                ":path": path,
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn set_broken(&self, url: &str, error: &str) -> Result<(), anyhow::Error> {
+        let sql = r#"
+            insert or replace into media (url, sha256, path, error)
+            values (:url, null, null, :error)
+        "#;
+        self.storage.conn.execute(
+            sql,
+            named_params! {
+                ":url": url,
+                ":error": error,
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn broken(&self) -> Result<Vec<MediaRecord>, anyhow::Error> {
+        let sql = "select url, sha256, path, error from media where error is not null order by url";
+        let mut stmt = self.storage.conn.prepare(sql)?;
+        let mut rows = stmt.query([])?;
+
+        let mut records = vec![];
+        while let Some(row) = rows.next()? {
+            records.push(MediaRecord::from_row(row)?);
+        }
+        Ok(records)
+    }
+}
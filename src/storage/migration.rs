@@ -0,0 +1,460 @@
+use crate::storage::post::{insert_post, insert_source};
+use crate::weibo::post::{MediaAsset, Post, PostSource};
+use log::info;
+use rusqlite::{named_params, Connection};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// 数据库的 schema 版本保存在 sqlite 的 user_version 中。
+// 打开数据库时，按顺序执行版本号大于当前版本的迁移，每个迁移在一个事务中完成。
+// 新增迁移时，在 MIGRATIONS 末尾追加即可，已有的迁移不要修改。
+
+struct Migration {
+    version: u32,
+    description: &'static str,
+    apply: fn(&Connection) -> Result<(), anyhow::Error>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create post, post_tombstone and settings tables",
+        apply: create_initial_tables,
+    },
+    Migration {
+        version: 2,
+        description: "normalize post table into columns, add user, picture and video tables",
+        apply: normalize_post_table,
+    },
+    Migration {
+        version: 3,
+        description: "add media table for archived pictures",
+        apply: create_media_table,
+    },
+    Migration {
+        version: 4,
+        description: "add picture_count column to post table",
+        apply: add_picture_count_column,
+    },
+    Migration {
+        version: 5,
+        description: "add raw_post table for compressed API payloads",
+        apply: create_raw_post_table,
+    },
+    Migration {
+        version: 6,
+        description: "add faved_at and unfaved_at columns to post table",
+        apply: add_fav_time_columns,
+    },
+    Migration {
+        version: 7,
+        description: "add post_source table, existing posts are from favorites",
+        apply: create_post_source_table,
+    },
+    Migration {
+        version: 8,
+        description: "add long_text_unavailable table",
+        apply: create_long_text_unavailable_table,
+    },
+];
+
+const SCHEMA_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+pub(super) fn migrate(conn: &Connection, path: &Path) -> Result<(), anyhow::Error> {
+    let version = schema_version(conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(anyhow::format_err!(
+            "database {} has schema version {}, but this weise only supports up to version {}, please upgrade weise",
+            path.display(),
+            version,
+            SCHEMA_VERSION
+        ));
+    }
+    // 版本 1 的微博数据转换失败时，表结构已是最新，但 post_v1 仍在，需要再次转换
+    if version == SCHEMA_VERSION && !has_table(conn, "post_v1")? {
+        return Ok(());
+    }
+
+    // 迁移已有的数据库之前，先备份
+    if version > 0 && version < SCHEMA_VERSION && path.is_file() {
+        let backup_path = backup_path(path, version);
+        fs::copy(path, &backup_path)?;
+        info!("backed up database to {}", backup_path.display());
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        info!(
+            "migrating database to version {}: {}",
+            migration.version, migration.description
+        );
+        let tx = conn.unchecked_transaction()?;
+        (migration.apply)(&tx)?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+    }
+
+    // 版本 1 的微博数据，在表结构都迁移完成之后再转换，这样可以直接使用当前的 insert_post。
+    // 转换在一个事务中完成，失败时 post_v1 保留，下次打开时重试
+    if has_table(conn, "post_v1")? {
+        let tx = conn.unchecked_transaction()?;
+        convert_blob_posts(&tx)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+pub(super) fn backup_path(path: &Path, version: u32) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(".v{}.bak", version));
+    path.with_file_name(file_name)
+}
+
+fn schema_version(conn: &Connection) -> Result<u32, anyhow::Error> {
+    let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > 0 || !has_table(conn, "post")? {
+        return Ok(version);
+    }
+
+    // 引入版本号之前创建的数据库，根据 post 表的结构判断版本
+    if has_column(conn, "post", "content")? {
+        Ok(1)
+    } else {
+        Ok(2)
+    }
+}
+
+fn has_table(conn: &Connection, table: &str) -> Result<bool, anyhow::Error> {
+    let sql = "select 1 from sqlite_master where type = 'table' and name = :table";
+    let mut stmt = conn.prepare(sql)?;
+    let exists = stmt.exists(named_params! {":table": table})?;
+    Ok(exists)
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool, anyhow::Error> {
+    let sql = format!(
+        "select 1 from pragma_table_info('{}') where name = :column",
+        table
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let exists = stmt.exists(named_params! {":column": column})?;
+    Ok(exists)
+}
+
+fn create_initial_tables(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.execute_batch(
+        r#"
+        create table if not exists post (
+            id integer primary key,
+            url text not null,
+            content text not null,
+            faved integer not null default 0
+        );
+
+        create table if not exists post_tombstone (
+            id integer primary key,
+            url text not null
+        );
+
+        create table if not exists settings (
+            name text unique,
+            value blob not null
+        );
+    "#,
+    )?;
+    Ok(())
+}
+
+// 版本 1 中，整条微博以 JSON 格式保存在 post 表的 content 列中。
+// 旧表改名为 post_v1，其中的数据由 convert_blob_posts 转换。
+// 此迁移最初也直接转换了数据，后来把转换移到了所有迁移之后，这是唯一一次修改已有的迁移：
+// 已是版本 2 及以上的数据库，转换早已完成且 post_v1 已删除，不受影响；
+// 版本 1 的数据库，迁移完成后得到的表和数据与修改之前相同(另外填充了 picture_count)
+fn normalize_post_table(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.execute_batch(
+        r#"
+        alter table post rename to post_v1;
+
+        create table user (
+            id integer primary key,
+            screen_name text not null
+        );
+
+        create table post (
+            id integer primary key,
+            mblogid text not null,
+            url text not null,
+            user_id integer not null,
+            text_raw text not null,
+            is_long_text integer not null,
+            long_text text,
+            media_type integer not null,
+            created_at integer not null,
+            visibility integer not null default 0,
+            retweeted_post_id integer,
+            faved integer not null default 0
+        );
+        create index post_url on post (url);
+        create index post_user_id on post (user_id);
+        create index post_created_at on post (created_at);
+
+        create table picture (
+            post_id integer not null,
+            position integer not null,
+            url text not null,
+            primary key (post_id, position)
+        );
+
+        create table video (
+            post_id integer primary key,
+            url text not null,
+            duration_secs integer not null,
+            cover_picture_url text not null
+        );
+    "#,
+    )?;
+    Ok(())
+}
+
+fn convert_blob_posts(conn: &Connection) -> Result<(), anyhow::Error> {
+    let mut count = 0;
+    {
+        let mut stmt = conn.prepare("select id, content, faved from post_v1 order by id")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let content: String = row.get(1)?;
+            let faved: bool = row.get(2)?;
+            let mut post: Post = serde_json::from_str(&content).map_err(|e| {
+                anyhow::format_err!("failed to convert post {} in post_v1: {}", id, e)
+            })?;
+            fill_picture_count(&mut post);
+            insert_post(conn, &post, faved)?;
+            if faved {
+                insert_source(conn, post.id, PostSource::Favorites)?;
+            }
+            count += 1;
+        }
+    }
+    conn.execute("drop table post_v1", [])?;
+    info!("migrated {} posts from the blob post table", count);
+    Ok(())
+}
+
+// 版本 1 的数据中没有 picture_count，只能以已有的图片数量代替
+fn fill_picture_count(post: &mut Post) {
+    if let MediaAsset::Pictures(picture_urls) = &post.media_asset {
+        post.picture_count = post.picture_count.max(picture_urls.len() as u32);
+    }
+    if let Some(retweeted_post) = &mut post.retweeted_post {
+        fill_picture_count(retweeted_post);
+    }
+}
+
+// 本地存档的图片。path 是相对于 media 目录的路径，下载失败时记录 error
+fn create_media_table(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.execute_batch(
+        r#"
+        create table media (
+            url text primary key,
+            sha256 text,
+            path text,
+            error text
+        );
+    "#,
+    )?;
+    Ok(())
+}
+
+fn add_picture_count_column(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.execute_batch(
+        r#"
+        alter table post add column picture_count integer not null default 0;
+        update post set picture_count = (
+            select count(*) from picture where picture.post_id = post.id
+        );
+    "#,
+    )?;
+    Ok(())
+}
+
+// content 是 snappy 压缩后的 JSON，见 RawPostStorage
+fn create_raw_post_table(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.execute_batch(
+        r#"
+        create table raw_post (
+            id integer primary key,
+            content blob not null
+        );
+    "#,
+    )?;
+    Ok(())
+}
+
+// 之前抓取的微博，收藏时间未知，faved_at 为 null
+fn add_fav_time_columns(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.execute_batch(
+        r#"
+        alter table post add column faved_at integer;
+        alter table post add column unfaved_at integer;
+    "#,
+    )?;
+    Ok(())
+}
+
+// source 为 PostSource::tag，比如 favorites、user:1773116334。
+// 在此之前只能抓取收藏，已有的微博都来自收藏
+fn create_post_source_table(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.execute_batch(
+        r#"
+        create table post_source (
+            post_id integer not null,
+            source text not null,
+            primary key (post_id, source)
+        );
+        create index post_source_source on post_source (source);
+        insert into post_source (post_id, source)
+            select id, 'favorites' from post where faved = 1;
+    "#,
+    )?;
+    Ok(())
+}
+
+// 是长微博，但全文接口没有返回全文的微博，补充全文时不再请求
+fn create_long_text_unavailable_table(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.execute_batch(
+        r#"
+        create table long_text_unavailable (
+            post_id integer primary key
+        );
+    "#,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Storage;
+    use crate::test_util::temp_dir;
+
+    fn create_v1_database(path: &Path) -> Result<(), anyhow::Error> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(include_str!("../../test_data/db_v1.sql"))?;
+        Ok(())
+    }
+
+    fn read_v1_posts(path: &Path) -> Result<Vec<Post>, anyhow::Error> {
+        let conn = Connection::open(path)?;
+        let mut stmt = conn.prepare("select content from post")?;
+        let mut rows = stmt.query([])?;
+        let mut posts = vec![];
+        while let Some(row) = rows.next()? {
+            let content: String = row.get(0)?;
+            posts.push(serde_json::from_str(&content)?);
+        }
+        Ok(posts)
+    }
+
+    #[test]
+    fn test_create_database() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        assert_eq!(schema_version(&storage.conn)?, SCHEMA_VERSION);
+        assert!(has_table(&storage.conn, "picture")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_upgrade_v1_database() -> Result<(), anyhow::Error> {
+        let path = temp_dir("migration").join("v1.db");
+        create_v1_database(&path)?;
+        let v1_posts = read_v1_posts(&path)?;
+
+        let storage = Storage::open(&path)?;
+        assert_eq!(schema_version(&storage.conn)?, SCHEMA_VERSION);
+        assert!(!has_table(&storage.conn, "post_v1")?);
+        assert!(!has_column(&storage.conn, "post", "content")?);
+
+        assert_eq!(storage.posts().count()?, 2);
+        for mut post in v1_posts {
+            fill_picture_count(&mut post);
+            if storage.posts().exists(post.id)? {
+                post.sources = vec![PostSource::Favorites];
+            }
+            assert_eq!(storage.posts().get_by_id(post.id)?, Some(post));
+        }
+        let text_post_id = 4723695598438753;
+        let picture_post_id = 4723131660373685;
+        let picture_post = storage.posts().get_by_id(picture_post_id)?.unwrap();
+        assert_eq!(picture_post.picture_count, 9);
+        assert!(!storage.posts().exists(picture_post_id)?);
+
+        assert!(storage
+            .post_tombstones()
+            .all_post_ids()?
+            .contains(&text_post_id));
+        assert_eq!(storage.settings().get_max_page()?, Some(42));
+
+        // 备份的是迁移前的数据库
+        let backup = Connection::open(backup_path(&path, 1))?;
+        assert!(has_column(&backup, "post", "content")?);
+        drop(backup);
+        drop(storage);
+
+        // 再次打开时不再迁移
+        let storage = Storage::open(&path)?;
+        assert_eq!(storage.posts().count()?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_converting_blob_posts() -> Result<(), anyhow::Error> {
+        let path = temp_dir("migration").join("v1_corrupt.db");
+        create_v1_database(&path)?;
+        let picture_retweet_id: i64 = 4723307730175522;
+        let content: String = {
+            let conn = Connection::open(&path)?;
+            let sql = "select content from post where id = :id";
+            let content =
+                conn.query_row(sql, named_params! {":id": picture_retweet_id}, |row| {
+                    row.get(0)
+                })?;
+            conn.execute(
+                "update post set content = 'not json' where id = :id",
+                named_params! {":id": picture_retweet_id},
+            )?;
+            content
+        };
+
+        let err = Storage::open(&path).err().unwrap();
+        assert!(err.to_string().contains(&picture_retweet_id.to_string()));
+        // 表结构已迁移，但 post_v1 仍在，再次打开时仍然转换，而不是当作空的存档
+        let err = Storage::open(&path).err().unwrap();
+        assert!(err.to_string().contains("post_v1"));
+
+        {
+            let conn = Connection::open(&path)?;
+            assert_eq!(schema_version(&conn)?, SCHEMA_VERSION);
+            conn.execute(
+                "update post_v1 set content = :content where id = :id",
+                named_params! {":content": content, ":id": picture_retweet_id},
+            )?;
+        }
+        let storage = Storage::open(&path)?;
+        assert!(!has_table(&storage.conn, "post_v1")?);
+        assert_eq!(storage.posts().count()?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuse_newer_database() -> Result<(), anyhow::Error> {
+        let path = temp_dir("migration").join("newer.db");
+        {
+            let conn = Connection::open(&path)?;
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)?;
+        }
+
+        let err = Storage::open(&path).err().unwrap();
+        assert!(err.to_string().contains("please upgrade weise"));
+        Ok(())
+    }
+}
@@ -1,3 +1,13 @@
+mod media;
+mod migration;
+mod post;
+mod raw_post;
+
+pub use media::{MediaRecord, MediaStorage};
+pub use post::PostStorage;
+pub use raw_post::RawPostStorage;
+
+use crate::crawler::SessionCookie;
 use crate::weibo::post::Post;
 use rusqlite::types::{FromSql, ToSql};
 use rusqlite::{named_params, Connection};
@@ -5,16 +15,12 @@ use std::collections::HashSet;
 use std::path::Path;
 
 // Storage 实际上是一个 sqlite 数据库。它包含以下表:
-// post, post_tombstone
+// post, user, picture, video, media, raw_post, post_tombstone, settings
 
 pub struct Storage {
     conn: Connection,
 }
 
-pub struct PostStorage<'a> {
-    storage: &'a Storage,
-}
-
 pub struct PostTombstoneStorage<'a> {
     storage: &'a Storage,
 }
@@ -31,34 +37,10 @@ impl Storage {
             PRAGMA temp_store = MEMORY;
         "#;
 
-        let post_table_creation = r#"
-            create table if not exists post (
-                id integer primary key,
-                url text not null,
-                content text not null,
-                faved integer not null default 0
-            );
-        "#;
-
-        let post_tombstone_table_creation = r#"
-            create table if not exists post_tombstone (
-                id integer primary key,
-                url text not null
-            );
-        "#;
-
-        let settings_table_creation = r#"
-            create table if not exists settings (
-                name text unique,
-                value blob not null
-            );
-        "#;
-
+        let path = path.as_ref();
         let conn = Connection::open(path)?;
         conn.execute_batch(pragma)?;
-        conn.execute(post_table_creation, [])?;
-        conn.execute(post_tombstone_table_creation, [])?;
-        conn.execute(settings_table_creation, [])?;
+        migration::migrate(&conn, path)?;
 
         Ok(Storage { conn })
     }
@@ -67,106 +49,20 @@ impl Storage {
         PostStorage { storage: self }
     }
 
-    pub fn post_tombstones(&self) -> PostTombstoneStorage<'_> {
-        PostTombstoneStorage { storage: self }
+    pub fn raw_posts(&self) -> RawPostStorage<'_> {
+        RawPostStorage { storage: self }
     }
 
-    pub fn settings(&self) -> SettingsStorage<'_> {
-        SettingsStorage { storage: self }
+    pub fn media(&self) -> MediaStorage<'_> {
+        MediaStorage { storage: self }
     }
-}
-
-impl<'a> PostStorage<'a> {
-    pub fn add(&self, post: &Post) -> Result<(), anyhow::Error> {
-        let sql = "insert or replace into post (id, url, content, faved) values (:id, :url, :content, :faved)";
 
-        let content = serde_json::to_string_pretty(post)?;
-        self.storage.conn.execute(
-            sql,
-            named_params! {
-                ":id": post.id,
-                ":url": post.url(),
-                ":content": content,
-                ":faved": 1,
-            },
-        )?;
-        Ok(())
-    }
-
-    pub fn batch_add(&mut self, posts: &[Post]) -> Result<(), anyhow::Error> {
-        let sql = "insert or replace into post (id, url, content, faved) values (:id, :url, :content, :faved)";
-        let tx = self.storage.conn.unchecked_transaction()?;
-        {
-            // seems NLL not working here.
-            let mut stmt = tx.prepare_cached(sql)?;
-            for post in posts {
-                let content = serde_json::to_string_pretty(post)?;
-                stmt.execute(named_params! {
-                    ":id": post.id,
-                    ":url": post.url(),
-                    ":content": content,
-                    ":faved": 1,
-                })?;
-            }
-        }
-        tx.commit()?;
-        Ok(())
-    }
-
-    pub fn get_posts(&self, since_id: i64, limit: usize) -> Result<Vec<Post>, anyhow::Error> {
-        let sql = "select content from post where id > :since_id order by id limit :limit";
-        let mut stmt = self.storage.conn.prepare(sql)?;
-        let mut rows = stmt.query(named_params! {
-            ":since_id": since_id,
-            ":limit": limit,
-        })?;
-
-        let mut posts = vec![];
-        while let Some(row) = rows.next()? {
-            let content: String = row.get(0)?;
-            let post: Post = serde_json::from_str(&content)?;
-            posts.push(post);
-        }
-        Ok(posts)
-    }
-
-    pub fn get_by_id(&self, post_id: i64) -> Result<Option<Post>, anyhow::Error> {
-        let mut stmt = self
-            .storage
-            .conn
-            .prepare("select content FROM post where id = :post_id")?;
-        let mut rows = stmt.query(named_params! {":post_id": post_id})?;
-        match rows.next() {
-            Ok(Some(row)) => {
-                let content: String = row.get(0)?;
-                let post: Post = serde_json::from_str(&content)?;
-                Ok(Some(post))
-            }
-            Ok(None) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
-    }
-
-    pub fn get_by_url(&self, url: &str) -> Result<Option<Post>, anyhow::Error> {
-        let mut stmt = self
-            .storage
-            .conn
-            .prepare("select content FROM post where url = :url")?;
-        let mut rows = stmt.query(named_params! {":url": url})?;
-        match rows.next() {
-            Ok(Some(row)) => {
-                let content: String = row.get(0)?;
-                let post: Post = serde_json::from_str(&content)?;
-                Ok(Some(post))
-            }
-            Ok(None) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
+    pub fn post_tombstones(&self) -> PostTombstoneStorage<'_> {
+        PostTombstoneStorage { storage: self }
     }
 
-    pub fn delete_all(&self) -> Result<(), anyhow::Error> {
-        self.storage.conn.execute("delete from post", [])?;
-        Ok(())
+    pub fn settings(&self) -> SettingsStorage<'_> {
+        SettingsStorage { storage: self }
     }
 }
 
@@ -244,16 +140,247 @@ impl<'a> SettingsStorage<'a> {
     }
 
     pub fn set_max_page(&self, max_page: u32) -> Result<(), anyhow::Error> {
-        self.set("max_page", &max_page)
+        self.set("max_page", max_page)
+    }
+
+    // 登录用户的 uid，抓取自己的微博及赞过的微博时需要
+    pub fn get_uid(&self) -> Result<Option<i64>, anyhow::Error> {
+        self.get("uid")
+    }
+
+    pub fn set_uid(&self, uid: i64) -> Result<(), anyhow::Error> {
+        self.set("uid", uid)
+    }
+
+    pub fn get_session_cookies(&self) -> Result<Option<Vec<SessionCookie>>, anyhow::Error> {
+        match self.get::<String>("session_cookies")? {
+            Some(content) => Ok(Some(serde_json::from_str(&content)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_session_cookies(&self, cookies: &[SessionCookie]) -> Result<(), anyhow::Error> {
+        let content = serde_json::to_string(cookies)?;
+        self.set("session_cookies", content)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), anyhow::Error> {
+        let sql = "delete from settings where name = :name";
+        self.storage
+            .conn
+            .execute(sql, named_params! {":name": name})?;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::load_post;
+    use crate::weibo::post::{weibo_timezone, MediaAsset, PostSource, PostVisibility, User};
+    use chrono::TimeZone;
     use std::fs;
     use std::path::Path;
 
+    fn all_posts() -> Vec<Post> {
+        vec![
+            load_post(include_str!("../../test_data/text.json")),
+            load_post(include_str!("../../test_data/picture.json")),
+            load_post(include_str!("../../test_data/video.json")),
+            load_post(include_str!("../../test_data/picture_retweet.json")),
+            load_post(include_str!("../../test_data/video_retweet.json")),
+            load_post(include_str!("../../test_data/retweet_censored.json")),
+        ]
+    }
+
+    #[test]
+    fn test_posts_round_trip() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        let posts = all_posts();
+        storage.posts().batch_add(&posts)?;
+
+        for post in &posts {
+            assert_eq!(storage.posts().get_by_id(post.id)?.as_ref(), Some(post));
+            assert_eq!(
+                storage.posts().get_by_url(&post.url())?.as_ref(),
+                Some(post)
+            );
+            assert!(storage.posts().exists(post.id)?);
+        }
+
+        // 被转发的原微博也保存了，但不算作收藏
+        let retweeted_post = posts[4].retweeted_post.as_ref().unwrap();
+        assert_eq!(
+            storage.posts().get_by_id(retweeted_post.id)?.as_ref(),
+            Some(retweeted_post.as_ref())
+        );
+        assert!(!storage.posts().exists(retweeted_post.id)?);
+        assert_eq!(storage.posts().count()?, posts.len());
+
+        let mut ids: Vec<i64> = posts.iter().map(|p| p.id).collect();
+        ids.sort_unstable();
+        let stored_ids: Vec<i64> = storage
+            .posts()
+            .get_posts(0, 100)?
+            .iter()
+            .map(|p| p.id)
+            .collect();
+        assert_eq!(stored_ids, ids);
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_long_text_and_faved() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        let mut post = load_post(include_str!("../../test_data/text.json"));
+        post.long_text = Some("全文".to_string());
+        storage.posts().add(&post)?;
+
+        // 再次抓取到的版本没有全文，之前获取的全文保留
+        post.long_text = None;
+        storage.posts().add(&post)?;
+        let stored = storage.posts().get_by_id(post.id)?.unwrap();
+        assert_eq!(stored.long_text.as_deref(), Some("全文"));
+
+        // 收藏的微博又作为被转发的微博出现，仍然是收藏
+        let mut retweet = load_post(include_str!("../../test_data/text_retweet.json"));
+        retweet.id += 1;
+        retweet.retweeted_post = Some(Box::new(post.clone()));
+        storage.posts().add(&retweet)?;
+        assert!(storage.posts().exists(post.id)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_archived_retweeted_post() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        let retweet = load_post(include_str!("../../test_data/text_retweet.json"));
+        let original = retweet.retweeted_post.clone().unwrap();
+        storage.posts().add(&retweet)?;
+
+        // 之后再抓取到时，原微博已被删除
+        let deleted_retweet = load_post(include_str!("../../test_data/retweet_deleted.json"));
+        storage.posts().add(&deleted_retweet)?;
+        let stored = storage.posts().get_by_id(original.id)?.unwrap();
+        assert_eq!(stored.visibility, PostVisibility::Deleted);
+        assert_eq!(stored.text_raw, original.text_raw);
+        assert_eq!(stored.user, original.user);
+
+        // 图片也保留
+        let retweet = load_post(include_str!("../../test_data/picture_retweet.json"));
+        let original = retweet.retweeted_post.clone().unwrap();
+        storage.posts().add(&retweet)?;
+        let mut censored_retweet = retweet.clone();
+        censored_retweet.id += 1;
+        censored_retweet.retweeted_post = Some(Box::new(Post {
+            user: User::default(),
+            text_raw: "该账号因被投诉违反法律法规和《微博社区公约》的相关规定，现已无法查看。"
+                .to_string(),
+            media_asset: MediaAsset::None,
+            picture_count: 0,
+            visibility: PostVisibility::Censored,
+            ..*original.clone()
+        }));
+        storage.posts().add(&censored_retweet)?;
+        let stored = storage.posts().get_by_id(original.id)?.unwrap();
+        assert_eq!(stored.visibility, PostVisibility::Censored);
+        assert_eq!(stored.media_asset, original.media_asset);
+        assert_eq!(stored.picture_count, original.picture_count);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_posts() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        let posts = all_posts();
+        storage.posts().batch_add(&posts)?;
+
+        let user_id = posts[0].user.id;
+        let by_user = storage.posts().get_posts_by_user(user_id, 0, 100)?;
+        assert!(!by_user.is_empty());
+        assert!(by_user.iter().all(|p| p.user.id == user_id));
+
+        let created_at = posts[0].created_at;
+        let since = weibo_timezone().timestamp(created_at.timestamp(), 0);
+        let until = weibo_timezone().timestamp(created_at.timestamp() + 1, 0);
+        let between = storage.posts().get_posts_created_between(since, until)?;
+        assert!(between.iter().any(|p| p.id == posts[0].id));
+        assert!(between.iter().all(|p| p.created_at == created_at));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mark_faved_and_unfaved() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        let posts = all_posts();
+        storage.posts().batch_add(&posts)?;
+        let day = |d: u32| weibo_timezone().ymd(2022, 2, d).and_hms(0, 0, 0);
+        let ids: Vec<i64> = posts.iter().map(|p| p.id).collect();
+
+        storage.posts().mark_faved(&ids[..4], day(1))?;
+        storage.posts().add_source(&ids, PostSource::Favorites)?;
+        // 已记录的收藏时间不会被之后的抓取覆盖
+        storage.posts().mark_faved(&ids[..1], day(2))?;
+        let post = storage.posts().get_by_id(ids[0])?.unwrap();
+        assert_eq!(post.faved_at, Some(day(1)));
+        assert_eq!(post.unfaved_at, None);
+        // 之前抓取的收藏，收藏时间未知，再次抓取到时也不记录
+        storage.posts().mark_faved(&ids[4..], day(2))?;
+        let post = storage.posts().get_by_id(ids[4])?.unwrap();
+        assert_eq!(post.faved_at, None);
+
+        let still_faved: HashSet<i64> = ids[1..].iter().copied().collect();
+        assert_eq!(
+            storage.posts().mark_unfaved_except(&still_faved, day(3))?,
+            1
+        );
+        assert_eq!(
+            storage.posts().mark_unfaved_except(&still_faved, day(4))?,
+            0
+        );
+        let post = storage.posts().get_by_id(ids[0])?.unwrap();
+        assert_eq!(post.unfaved_at, Some(day(3)));
+        assert!(storage.posts().exists(ids[0])?);
+        assert_eq!(storage.posts().count()?, posts.len());
+        // 被转发的原微博不是收藏
+        let retweeted_post_id = posts[4].retweeted_post.as_ref().unwrap().id;
+        let retweeted_post = storage.posts().get_by_id(retweeted_post_id)?.unwrap();
+        assert_eq!(retweeted_post.unfaved_at, None);
+
+        // 写入微博时保留收藏状态；再次收藏时重新记录收藏时间
+        storage.posts().add(&posts[0])?;
+        assert_eq!(
+            storage.posts().get_by_id(ids[0])?.unwrap().unfaved_at,
+            Some(day(3))
+        );
+        storage.posts().mark_faved(&ids[..1], day(5))?;
+        let post = storage.posts().get_by_id(ids[0])?.unwrap();
+        assert_eq!(post.faved_at, Some(day(5)));
+        assert_eq!(post.unfaved_at, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_posts_round_trip() -> Result<(), anyhow::Error> {
+        let storage = Storage::open(":memory:")?;
+        let text: serde_json::Value =
+            serde_json::from_str(include_str!("../../test_data/text.json"))?;
+        let video: serde_json::Value =
+            serde_json::from_str(include_str!("../../test_data/video.json"))?;
+        storage.raw_posts().batch_add([(1, &text), (2, &video)])?;
+        // 再次保存时覆盖
+        storage.raw_posts().batch_add([(1, &video)])?;
+
+        assert_eq!(storage.raw_posts().count()?, 2);
+        assert_eq!(storage.raw_posts().get(1)?, Some(video.clone()));
+        assert!(storage.raw_posts().get(3)?.is_none());
+        assert_eq!(
+            storage.raw_posts().get_raw_posts(1, 10)?,
+            vec![(2, video.clone())]
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_max_page_settings() {
         let dbfile = Path::new("db.db");
@@ -266,4 +393,28 @@ mod tests {
         storage.settings().set_max_page(123).unwrap();
         assert_eq!(storage.settings().get_max_page().unwrap(), Some(123));
     }
+
+    #[test]
+    fn test_session_cookies_settings() {
+        let storage = Storage::open(":memory:").unwrap();
+        assert!(storage.settings().get_session_cookies().unwrap().is_none());
+
+        let cookies = vec![SessionCookie {
+            name: "SUB".to_string(),
+            value: "abc".to_string(),
+            domain: Some(".weibo.com".to_string()),
+            path: Some("/".to_string()),
+            secure: Some(true),
+            http_only: Some(true),
+            expiry: None,
+        }];
+        storage.settings().set_session_cookies(&cookies).unwrap();
+        assert_eq!(
+            storage.settings().get_session_cookies().unwrap(),
+            Some(cookies)
+        );
+
+        storage.settings().delete("session_cookies").unwrap();
+        assert!(storage.settings().get_session_cookies().unwrap().is_none());
+    }
 }
@@ -0,0 +1,487 @@
+use crate::storage::Storage;
+use crate::weibo::post::{
+    weibo_timezone, MediaAsset, Post, PostSource, PostVisibility, User, VideoEntry,
+};
+use chrono::{DateTime, FixedOffset, TimeZone};
+use rusqlite::{named_params, Connection, Row};
+use std::collections::HashSet;
+
+// post 表中，faved 为 1 的是抓取到的微博，其来源(收藏、赞过的微博等)保存在 post_source 表中；
+// 被转发的原微博也会保存在 post 表中，其 faved 为 0。
+// 取消收藏的微博仍然保留，faved 仍为 1，unfaved_at 为发现其取消收藏的时间。
+// faved_at、unfaved_at 及来源只由 mark_faved、mark_unfaved_except 和 add_source 更新，写入微博时忽略。
+// 图片和视频分别保存在 picture 和 video 表中，作者保存在 user 表中。
+
+pub struct PostStorage<'a> {
+    pub(super) storage: &'a Storage,
+}
+
+const SELECT_POST: &str = r#"
+    select post.id, post.mblogid, post.user_id, user.screen_name, post.text_raw,
+        post.is_long_text, post.long_text, post.created_at, post.visibility,
+        post.retweeted_post_id, post.picture_count, post.faved_at, post.unfaved_at
+    from post left join user on post.user_id = user.id
+"#;
+
+impl<'a> PostStorage<'a> {
+    pub fn add(&self, post: &Post) -> Result<(), anyhow::Error> {
+        let tx = self.storage.conn.unchecked_transaction()?;
+        insert_post(&tx, post, true)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn batch_add(&mut self, posts: &[Post]) -> Result<(), anyhow::Error> {
+        let tx = self.storage.conn.unchecked_transaction()?;
+        for post in posts {
+            insert_post(&tx, post, true)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // 按 id 顺序返回抓取到的微博(不包括被转发的原微博)
+    pub fn get_posts(&self, since_id: i64, limit: usize) -> Result<Vec<Post>, anyhow::Error> {
+        let sql = format!(
+            "{} where post.faved = 1 and post.id > :since_id order by post.id limit :limit",
+            SELECT_POST
+        );
+        self.query_posts(
+            &sql,
+            named_params! {
+                ":since_id": since_id,
+                ":limit": limit,
+            },
+        )
+    }
+
+    pub fn get_posts_by_user(
+        &self,
+        user_id: i64,
+        since_id: i64,
+        limit: usize,
+    ) -> Result<Vec<Post>, anyhow::Error> {
+        let sql = format!(
+            "{} where post.faved = 1 and post.user_id = :user_id and post.id > :since_id order by post.id limit :limit",
+            SELECT_POST
+        );
+        self.query_posts(
+            &sql,
+            named_params! {
+                ":user_id": user_id,
+                ":since_id": since_id,
+                ":limit": limit,
+            },
+        )
+    }
+
+    // 发布时间在 [since, until) 之间的收藏微博，按发布时间排序
+    pub fn get_posts_created_between(
+        &self,
+        since: DateTime<FixedOffset>,
+        until: DateTime<FixedOffset>,
+    ) -> Result<Vec<Post>, anyhow::Error> {
+        let sql = format!(
+            "{} where post.faved = 1 and post.created_at >= :since and post.created_at < :until order by post.created_at",
+            SELECT_POST
+        );
+        self.query_posts(
+            &sql,
+            named_params! {
+                ":since": since.timestamp(),
+                ":until": until.timestamp(),
+            },
+        )
+    }
+
+    // 抓取到的收藏微博。第一次从收藏中抓取到，或者取消收藏之后又被收藏时，记录收藏时间。
+    // 之前抓取的收藏微博，收藏时间未知，faved_at 仍为 null。
+    // 以是否已有 favorites 来源判断是否第一次抓取到，所以需要在 add_source 之前调用
+    pub fn mark_faved(
+        &self,
+        post_ids: &[i64],
+        faved_at: DateTime<FixedOffset>,
+    ) -> Result<(), anyhow::Error> {
+        let sql = r#"
+            update post set
+                faved_at = case
+                    when unfaved_at is not null then :faved_at
+                    when not exists (
+                        select 1 from post_source
+                        where post_source.post_id = post.id and post_source.source = :source
+                    ) then :faved_at
+                    else faved_at
+                end,
+                unfaved_at = null
+            where id = :post_id and faved = 1
+        "#;
+        let tx = self.storage.conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(sql)?;
+            for post_id in post_ids {
+                stmt.execute(named_params! {
+                    ":post_id": post_id,
+                    ":faved_at": faved_at.timestamp(),
+                    ":source": PostSource::Favorites.tag(),
+                })?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // 完整同步收藏之后，不在 faved_ids 中的收藏微博即是已取消收藏的。返回新标记的数量
+    pub fn mark_unfaved_except(
+        &self,
+        faved_ids: &HashSet<i64>,
+        unfaved_at: DateTime<FixedOffset>,
+    ) -> Result<usize, anyhow::Error> {
+        let tx = self.storage.conn.unchecked_transaction()?;
+        let mut count = 0;
+        {
+            let sql = r#"
+                select post.id from post join post_source on post.id = post_source.post_id
+                where post_source.source = :source and post.unfaved_at is null
+            "#;
+            let mut stmt = tx.prepare(sql)?;
+            let source = PostSource::Favorites.tag();
+            let post_ids: Vec<i64> = stmt
+                .query_map(named_params! {":source": source}, |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+
+            let sql = "update post set unfaved_at = :unfaved_at where id = :post_id";
+            let mut stmt = tx.prepare_cached(sql)?;
+            for post_id in post_ids.iter().filter(|id| !faved_ids.contains(id)) {
+                stmt.execute(named_params! {
+                    ":post_id": post_id,
+                    ":unfaved_at": unfaved_at.timestamp(),
+                })?;
+                count += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
+    pub fn add_source(&self, post_ids: &[i64], source: PostSource) -> Result<(), anyhow::Error> {
+        let tx = self.storage.conn.unchecked_transaction()?;
+        for post_id in post_ids {
+            insert_source(&tx, *post_id, source)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // 增量抓取时，以此判断微博是否已从该来源抓取过
+    pub fn has_source(&self, post_id: i64, source: PostSource) -> Result<bool, anyhow::Error> {
+        let sql = "select 1 from post_source where post_id = :post_id and source = :source";
+        let mut stmt = self.storage.conn.prepare_cached(sql)?;
+        let exists = stmt.exists(named_params! {
+            ":post_id": post_id,
+            ":source": source.tag(),
+        })?;
+        Ok(exists)
+    }
+
+    // 全文接口没有返回全文的长微博，之后补充全文时跳过
+    pub fn mark_long_text_unavailable(&self, post_ids: &[i64]) -> Result<(), anyhow::Error> {
+        let sql = "insert or ignore into long_text_unavailable (post_id) values (:post_id)";
+        let tx = self.storage.conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(sql)?;
+            for post_id in post_ids {
+                stmt.execute(named_params! {":post_id": post_id})?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn long_text_unavailable_ids(&self) -> Result<HashSet<i64>, anyhow::Error> {
+        let sql = "select post_id from long_text_unavailable";
+        let mut stmt = self.storage.conn.prepare(sql)?;
+        let post_ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(post_ids)
+    }
+
+    pub fn count(&self) -> Result<usize, anyhow::Error> {
+        let sql = "select count(*) from post where faved = 1";
+        let count: usize = self.storage.conn.query_row(sql, [], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    pub fn exists(&self, post_id: i64) -> Result<bool, anyhow::Error> {
+        let sql = "select 1 from post where id = :post_id and faved = 1";
+        let mut stmt = self.storage.conn.prepare_cached(sql)?;
+        let exists = stmt.exists(named_params! {":post_id": post_id})?;
+        Ok(exists)
+    }
+
+    pub fn get_by_id(&self, post_id: i64) -> Result<Option<Post>, anyhow::Error> {
+        let sql = format!("{} where post.id = :post_id", SELECT_POST);
+        let posts = self.query_posts(&sql, named_params! {":post_id": post_id})?;
+        Ok(posts.into_iter().next())
+    }
+
+    pub fn get_by_url(&self, url: &str) -> Result<Option<Post>, anyhow::Error> {
+        let sql = format!("{} where post.url = :url", SELECT_POST);
+        let posts = self.query_posts(&sql, named_params! {":url": url})?;
+        Ok(posts.into_iter().next())
+    }
+
+    pub fn delete_all(&self) -> Result<(), anyhow::Error> {
+        self.storage.conn.execute_batch(
+            r#"
+            delete from picture;
+            delete from video;
+            delete from post_source;
+            delete from long_text_unavailable;
+            delete from post;
+            delete from user;
+        "#,
+        )?;
+        Ok(())
+    }
+
+    fn query_posts<P: rusqlite::Params>(
+        &self,
+        sql: &str,
+        params: P,
+    ) -> Result<Vec<Post>, anyhow::Error> {
+        let conn = &self.storage.conn;
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows = stmt.query(params)?;
+
+        let mut post_rows = vec![];
+        while let Some(row) = rows.next()? {
+            post_rows.push(PostRow::from_row(row)?);
+        }
+
+        let mut posts = vec![];
+        for post_row in post_rows {
+            posts.push(assemble_post(conn, post_row)?);
+        }
+        Ok(posts)
+    }
+}
+
+struct PostRow {
+    post: Post,
+    retweeted_post_id: Option<i64>,
+}
+
+impl PostRow {
+    fn from_row(row: &Row) -> Result<PostRow, anyhow::Error> {
+        let created_at: i64 = row.get(7)?;
+        let visibility: u8 = row.get(8)?;
+        let timestamp = |t: Option<i64>| t.map(|t| weibo_timezone().timestamp(t, 0));
+        let post = Post {
+            id: row.get(0)?,
+            mblogid: row.get(1)?,
+            user: User {
+                id: row.get(2)?,
+                screen_name: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+            },
+            text_raw: row.get(4)?,
+            is_long_text: row.get(5)?,
+            long_text: row.get(6)?,
+            media_asset: MediaAsset::None,
+            picture_count: row.get(10)?,
+            created_at: weibo_timezone().timestamp(created_at, 0),
+            visibility: PostVisibility::from_u8(visibility).unwrap_or_default(),
+            faved_at: timestamp(row.get(11)?),
+            unfaved_at: timestamp(row.get(12)?),
+            sources: vec![],
+            retweeted_post: None,
+        };
+        Ok(PostRow {
+            post,
+            retweeted_post_id: row.get(9)?,
+        })
+    }
+}
+
+fn assemble_post(conn: &Connection, post_row: PostRow) -> Result<Post, anyhow::Error> {
+    let mut post = post_row.post;
+    post.media_asset = load_media_asset(conn, post.id)?;
+    post.sources = load_sources(conn, post.id)?;
+    if let Some(retweeted_post_id) = post_row.retweeted_post_id {
+        let sql = format!("{} where post.id = :post_id", SELECT_POST);
+        let mut stmt = conn.prepare_cached(&sql)?;
+        let mut rows = stmt.query(named_params! {":post_id": retweeted_post_id})?;
+        if let Some(row) = rows.next()? {
+            let retweeted_post = assemble_post(conn, PostRow::from_row(row)?)?;
+            post.retweeted_post = Some(Box::new(retweeted_post));
+        }
+    }
+    Ok(post)
+}
+
+fn load_media_asset(conn: &Connection, post_id: i64) -> Result<MediaAsset, anyhow::Error> {
+    let sql = "select url, duration_secs, cover_picture_url from video where post_id = :post_id";
+    let mut stmt = conn.prepare_cached(sql)?;
+    let mut rows = stmt.query(named_params! {":post_id": post_id})?;
+    if let Some(row) = rows.next()? {
+        return Ok(MediaAsset::Video(VideoEntry {
+            url: row.get(0)?,
+            duration_secs: row.get(1)?,
+            cover_picture_url: row.get(2)?,
+        }));
+    }
+
+    let sql = "select url from picture where post_id = :post_id order by position";
+    let mut stmt = conn.prepare_cached(sql)?;
+    let mut rows = stmt.query(named_params! {":post_id": post_id})?;
+    let mut picture_urls = vec![];
+    while let Some(row) = rows.next()? {
+        picture_urls.push(row.get(0)?);
+    }
+    if picture_urls.is_empty() {
+        Ok(MediaAsset::None)
+    } else {
+        Ok(MediaAsset::Pictures(picture_urls))
+    }
+}
+
+fn load_sources(conn: &Connection, post_id: i64) -> Result<Vec<PostSource>, anyhow::Error> {
+    let sql = "select source from post_source where post_id = :post_id order by source";
+    let mut stmt = conn.prepare_cached(sql)?;
+    let mut rows = stmt.query(named_params! {":post_id": post_id})?;
+    let mut sources = vec![];
+    while let Some(row) = rows.next()? {
+        let tag: String = row.get(0)?;
+        sources.push(tag.parse()?);
+    }
+    Ok(sources)
+}
+
+pub(super) fn insert_source(
+    conn: &Connection,
+    post_id: i64,
+    source: PostSource,
+) -> Result<(), anyhow::Error> {
+    let sql = "insert or ignore into post_source (post_id, source) values (:post_id, :source)";
+    conn.prepare_cached(sql)?.execute(named_params! {
+        ":post_id": post_id,
+        ":source": source.tag(),
+    })?;
+    Ok(())
+}
+
+fn is_stored_visible(conn: &Connection, post_id: i64) -> Result<bool, anyhow::Error> {
+    let sql = "select 1 from post where id = :post_id and visibility = :visibility";
+    let mut stmt = conn.prepare_cached(sql)?;
+    let visible = stmt.exists(named_params! {
+        ":post_id": post_id,
+        ":visibility": PostVisibility::Visible as u8,
+    })?;
+    Ok(visible)
+}
+
+// 被转发的原微博一并保存，faved 为 0。同一条微博既被收藏又被转发时，faved 保持为 1。
+pub(super) fn insert_post(
+    conn: &Connection,
+    post: &Post,
+    faved: bool,
+) -> Result<(), anyhow::Error> {
+    if let Some(retweeted_post) = &post.retweeted_post {
+        insert_post(conn, retweeted_post, false)?;
+    }
+
+    // 已存档的可见微博，之后被删除或变为不可见时，只更新 visibility，保留已存档的正文、作者、图片和视频
+    if post.visibility != PostVisibility::Visible && is_stored_visible(conn, post.id)? {
+        let sql = r#"
+            update post set visibility = :visibility, faved = max(faved, :faved)
+            where id = :id
+        "#;
+        conn.prepare_cached(sql)?.execute(named_params! {
+            ":id": post.id,
+            ":visibility": post.visibility as u8,
+            ":faved": faved,
+        })?;
+        return Ok(());
+    }
+
+    if post.user.id != 0 {
+        let sql = r#"
+            insert into user (id, screen_name) values (:id, :screen_name)
+            on conflict(id) do update set
+                screen_name = coalesce(nullif(excluded.screen_name, ''), user.screen_name)
+        "#;
+        conn.prepare_cached(sql)?.execute(named_params! {
+            ":id": post.user.id,
+            ":screen_name": post.user.screen_name,
+        })?;
+    }
+
+    // 长微博全文获取之后，不会被没有全文的版本覆盖
+    let sql = r#"
+        insert into post (id, mblogid, url, user_id, text_raw, is_long_text, long_text,
+            media_type, picture_count, created_at, visibility, retweeted_post_id, faved)
+        values (:id, :mblogid, :url, :user_id, :text_raw, :is_long_text, :long_text,
+            :media_type, :picture_count, :created_at, :visibility, :retweeted_post_id, :faved)
+        on conflict(id) do update set
+            mblogid = excluded.mblogid,
+            url = excluded.url,
+            user_id = excluded.user_id,
+            text_raw = excluded.text_raw,
+            is_long_text = excluded.is_long_text,
+            long_text = coalesce(excluded.long_text, post.long_text),
+            media_type = excluded.media_type,
+            picture_count = excluded.picture_count,
+            created_at = excluded.created_at,
+            visibility = excluded.visibility,
+            retweeted_post_id = excluded.retweeted_post_id,
+            faved = max(post.faved, excluded.faved)
+    "#;
+    conn.prepare_cached(sql)?.execute(named_params! {
+        ":id": post.id,
+        ":mblogid": post.mblogid,
+        ":url": post.url(),
+        ":user_id": post.user.id,
+        ":text_raw": post.text_raw,
+        ":is_long_text": post.is_long_text,
+        ":long_text": post.long_text,
+        ":media_type": post.media_type() as u8,
+        ":picture_count": post.picture_count,
+        ":created_at": post.created_at.timestamp(),
+        ":visibility": post.visibility as u8,
+        ":retweeted_post_id": post.retweeted_post.as_ref().map(|p| p.id),
+        ":faved": faved,
+    })?;
+
+    conn.prepare_cached("delete from picture where post_id = :post_id")?
+        .execute(named_params! {":post_id": post.id})?;
+    conn.prepare_cached("delete from video where post_id = :post_id")?
+        .execute(named_params! {":post_id": post.id})?;
+    match &post.media_asset {
+        MediaAsset::None => {}
+        MediaAsset::Pictures(picture_urls) => {
+            let sql =
+                "insert into picture (post_id, position, url) values (:post_id, :position, :url)";
+            let mut stmt = conn.prepare_cached(sql)?;
+            for (position, url) in picture_urls.iter().enumerate() {
+                stmt.execute(named_params! {
+                    ":post_id": post.id,
+                    ":position": position,
+                    ":url": url,
+                })?;
+            }
+        }
+        MediaAsset::Video(video) => {
+            let sql = r#"
+                insert into video (post_id, url, duration_secs, cover_picture_url)
+                values (:post_id, :url, :duration_secs, :cover_picture_url)
+            "#;
+            conn.prepare_cached(sql)?.execute(named_params! {
+                ":post_id": post.id,
+                ":url": video.url,
+                ":duration_secs": video.duration_secs,
+                ":cover_picture_url": video.cover_picture_url,
+            })?;
+        }
+    }
+    Ok(())
+}
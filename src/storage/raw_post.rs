@@ -0,0 +1,78 @@
+use crate::storage::Storage;
+use rusqlite::named_params;
+use serde_json::Value;
+
+// raw_post 表保存收藏接口返回的每条微博的原始 JSON，以 snappy 压缩。
+// 改进 RawPost::normalize 之后，可以用 weise renormalize 从中重新生成 post 表的数据。
+
+pub struct RawPostStorage<'a> {
+    pub(super) storage: &'a Storage,
+}
+
+impl<'a> RawPostStorage<'a> {
+    pub fn batch_add<'v, I>(&self, raw_posts: I) -> Result<(), anyhow::Error>
+    where
+        I: IntoIterator<Item = (i64, &'v Value)>,
+    {
+        let tx = self.storage.conn.unchecked_transaction()?;
+        {
+            let sql = "insert or replace into raw_post (id, content) values (:id, :content)";
+            let mut stmt = tx.prepare_cached(sql)?;
+            for (post_id, raw) in raw_posts {
+                stmt.execute(named_params! {
+                    ":id": post_id,
+                    ":content": compress(raw)?,
+                })?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get(&self, post_id: i64) -> Result<Option<Value>, anyhow::Error> {
+        let sql = "select content from raw_post where id = :id";
+        let mut stmt = self.storage.conn.prepare_cached(sql)?;
+        let mut rows = stmt.query(named_params! {":id": post_id})?;
+        match rows.next()? {
+            Some(row) => Ok(Some(decompress(&row.get::<_, Vec<u8>>(0)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    // 按 id 顺序返回
+    pub fn get_raw_posts(
+        &self,
+        since_id: i64,
+        limit: usize,
+    ) -> Result<Vec<(i64, Value)>, anyhow::Error> {
+        let sql = "select id, content from raw_post where id > :since_id order by id limit :limit";
+        let mut stmt = self.storage.conn.prepare_cached(sql)?;
+        let mut rows = stmt.query(named_params! {
+            ":since_id": since_id,
+            ":limit": limit,
+        })?;
+
+        let mut raw_posts = vec![];
+        while let Some(row) = rows.next()? {
+            let content: Vec<u8> = row.get(1)?;
+            raw_posts.push((row.get(0)?, decompress(&content)?));
+        }
+        Ok(raw_posts)
+    }
+
+    pub fn count(&self) -> Result<usize, anyhow::Error> {
+        let sql = "select count(*) from raw_post";
+        let count: usize = self.storage.conn.query_row(sql, [], |row| row.get(0))?;
+        Ok(count)
+    }
+}
+
+fn compress(raw: &Value) -> Result<Vec<u8>, anyhow::Error> {
+    let json = serde_json::to_vec(raw)?;
+    Ok(snap::raw::Encoder::new().compress_vec(&json)?)
+}
+
+fn decompress(content: &[u8]) -> Result<Value, anyhow::Error> {
+    let json = snap::raw::Decoder::new().decompress_vec(content)?;
+    Ok(serde_json::from_slice(&json)?)
+}
@@ -0,0 +1,144 @@
+// 测试用的简易 HTTP 服务器，用于模拟 WebDriver 以及微博的接口。
+use crate::weibo::post::Post;
+use crate::weibo::raw::RawPost;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+// 从 test_data 中的 JSON 解析并规范化微博
+pub fn load_post(json: &str) -> Post {
+    let raw: RawPost = serde_json::from_str(json).unwrap();
+    raw.normalize()
+}
+
+// 每个测试进程独立的临时目录，name 需要在测试之间唯一
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("weise-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub struct FakeRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub struct FakeResponse {
+    pub status: u16,
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+impl FakeResponse {
+    pub fn json(value: serde_json::Value) -> FakeResponse {
+        FakeResponse {
+            status: 200,
+            content_type: "application/json".to_string(),
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    pub fn bytes(content_type: &str, body: &[u8]) -> FakeResponse {
+        FakeResponse {
+            status: 200,
+            content_type: content_type.to_string(),
+            body: body.to_vec(),
+        }
+    }
+
+    pub fn not_found() -> FakeResponse {
+        FakeResponse {
+            status: 404,
+            content_type: "text/plain".to_string(),
+            body: b"not found".to_vec(),
+        }
+    }
+}
+
+pub struct FakeHttpServer {
+    port: u16,
+}
+
+impl FakeHttpServer {
+    // 服务器线程随测试进程结束而结束
+    pub fn start<F>(handler: F) -> FakeHttpServer
+    where
+        F: Fn(&FakeRequest) -> FakeResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handler = Arc::new(handler);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let handler = handler.clone();
+                thread::spawn(move || {
+                    let _ = serve_connection(stream, handler.as_ref());
+                });
+            }
+        });
+        FakeHttpServer { port }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+}
+
+fn serve_connection<F>(stream: TcpStream, handler: &F) -> std::io::Result<()>
+where
+    F: Fn(&FakeRequest) -> FakeResponse,
+{
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line)? == 0 {
+            return Ok(());
+        }
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+            }
+        }
+        let content_length: usize = headers
+            .get("content-length")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let request = FakeRequest {
+            method,
+            path,
+            headers,
+            body,
+        };
+        let response = handler(&request);
+        write!(
+            writer,
+            "HTTP/1.1 {} OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            response.status,
+            response.content_type,
+            response.body.len()
+        )?;
+        writer.write_all(&response.body)?;
+        writer.flush()?;
+    }
+}
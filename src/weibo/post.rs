@@ -8,8 +8,25 @@ pub struct Post {
     pub user: User,
     pub text_raw: String,
     pub is_long_text: bool,
+    // 长微博的全文。text_raw 中只有开头的一部分，全文需要另外请求
+    #[serde(default)]
+    pub long_text: Option<String>,
     pub media_asset: MediaAsset,
+    // 图片的数量。图片超过 9 张时，可能有图片的 url 缺失，以此为准
+    #[serde(default)]
+    pub picture_count: u32,
     pub created_at: DateTime<FixedOffset>,
+    #[serde(default)]
+    pub visibility: PostVisibility,
+    // 收藏的时间。收藏接口不返回收藏时间，以第一次抓取到的时间代替，见 PostStorage::mark_faved
+    #[serde(default)]
+    pub faved_at: Option<DateTime<FixedOffset>>,
+    // 完整同步收藏时发现已取消收藏的时间。取消收藏的微博仍然保留在存档中
+    #[serde(default)]
+    pub unfaved_at: Option<DateTime<FixedOffset>>,
+    // 抓取到此微博的来源，一条微博可能同时在多个来源中。被转发的原微博没有来源
+    #[serde(default)]
+    pub sources: Vec<PostSource>,
 
     pub retweeted_post: Option<Box<Post>>,
 }
@@ -22,7 +39,7 @@ pub struct User {
     pub screen_name: String,
 }
 
-#[derive(Debug, PartialEq, Clone, Copy, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize, Serialize, clap::ArgEnum)]
 #[repr(u8)]
 pub enum MediaType {
     Text = 0,
@@ -30,6 +47,92 @@ pub enum MediaType {
     Video = 2,
 }
 
+// 微博的可见状态。不可见的微博，text_raw 中是微博给出的提示文字
+#[derive(Debug, PartialEq, Clone, Copy, Default, Deserialize, Serialize, clap::ArgEnum)]
+#[repr(u8)]
+pub enum PostVisibility {
+    #[default]
+    Visible = 0,
+    // 被作者删除
+    Deleted = 1,
+    // 因作者设置而不可见，比如「仅半年内可见」
+    Restricted = 2,
+    // 因监管原因不可见
+    Censored = 3,
+}
+
+impl MediaType {
+    pub fn from_u8(value: u8) -> Option<MediaType> {
+        match value {
+            0 => Some(MediaType::Text),
+            1 => Some(MediaType::Picture),
+            2 => Some(MediaType::Video),
+            _ => None,
+        }
+    }
+}
+
+impl PostVisibility {
+    pub fn from_u8(value: u8) -> Option<PostVisibility> {
+        match value {
+            0 => Some(PostVisibility::Visible),
+            1 => Some(PostVisibility::Deleted),
+            2 => Some(PostVisibility::Restricted),
+            3 => Some(PostVisibility::Censored),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PostVisibility::Visible => "可见",
+            PostVisibility::Deleted => "已删除",
+            PostVisibility::Restricted => "作者设置不可见",
+            PostVisibility::Censored => "无法查看",
+        }
+    }
+}
+
+// 抓取微博的来源: 收藏、自己发布的微博、赞过的微博，或者某个用户的时间线
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Deserialize, Serialize)]
+pub enum PostSource {
+    Favorites,
+    Mine,
+    Likes,
+    User(i64),
+}
+
+impl PostSource {
+    // 命令行参数及数据库中使用的名字，比如 favorites、user:1773116334
+    pub fn tag(&self) -> String {
+        match self {
+            PostSource::Favorites => "favorites".to_string(),
+            PostSource::Mine => "mine".to_string(),
+            PostSource::Likes => "likes".to_string(),
+            PostSource::User(uid) => format!("user:{}", uid),
+        }
+    }
+}
+
+impl std::str::FromStr for PostSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<PostSource, anyhow::Error> {
+        match s {
+            "favorites" => Ok(PostSource::Favorites),
+            "mine" => Ok(PostSource::Mine),
+            "likes" => Ok(PostSource::Likes),
+            _ => match s.strip_prefix("user:").map(|uid| uid.parse::<i64>()) {
+                Some(Ok(uid)) if uid > 0 => Ok(PostSource::User(uid)),
+                _ => Err(anyhow::format_err!(
+                    "invalid source {:?}, expected favorites, mine, likes or user:<uid>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
 pub enum MediaAsset {
     None,
@@ -44,6 +147,11 @@ pub struct VideoEntry {
     pub cover_picture_url: String,
 }
 
+// 微博的时间均为东八区时间
+pub fn weibo_timezone() -> FixedOffset {
+    FixedOffset::east(8 * 3600)
+}
+
 impl Post {
     pub fn url(&self) -> String {
         format!("https://weibo.com/{}/{}", self.user.id, self.mblogid)
@@ -56,12 +164,47 @@ impl Post {
         }
     }
 
+    // 微博全文。长微博的全文尚未获取时，只能返回 text_raw
+    pub fn full_text(&self) -> &str {
+        match &self.long_text {
+            Some(long_text) => long_text,
+            None => &self.text_raw,
+        }
+    }
+
+    // 此微博或者被转发的微博是长微博，且全文尚未获取
+    pub fn needs_long_text(&self) -> bool {
+        if self.is_long_text && self.long_text.is_none() {
+            return true;
+        }
+        match &self.retweeted_post {
+            Some(p) => p.needs_long_text(),
+            None => false,
+        }
+    }
+
     pub fn is_retweet(&self) -> bool {
         self.retweeted_post.is_some()
     }
 
+    // 此微博或者被转发的微博中，第一个不可见的状态
+    pub fn overall_visibility(&self) -> PostVisibility {
+        if self.visibility != PostVisibility::Visible {
+            return self.visibility;
+        }
+        match &self.retweeted_post {
+            Some(p) => p.overall_visibility(),
+            None => PostVisibility::Visible,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visibility == PostVisibility::Visible
+    }
+
     pub fn is_valid(&self) -> bool {
-        if self.user.id == 0 {
+        // 不可见的微博，通常没有 user 信息
+        if self.user.id == 0 && self.is_visible() {
             return false;
         }
         if let Some(retweeted_post) = &self.retweeted_post {
@@ -106,12 +249,18 @@ mod tests {
             id: 4723695598438753,
             mblogid: "L9WqHzpiV".to_string(),
             user,
-            text_raw: "今年我一定会开一家新公司以 GraalVM 为工具研发几个产品，目前产品思路逐渐清晰，长中短期都有，不会再像过去十年研究数据库那么耗时了，搞数据库基础理论创新实在是太硬核了，没有好的思路半年都没啥进展。[允悲] ​​​".to_string(),
+            text_raw: "今年我一定会开一家新公司以 GraalVM 为工具研发几个产品，目前产品思路逐渐清晰，长中短期都有，不会再像过去十年研究数据库那么耗时了，搞数据库基础理论创新实在是太硬核了，没有好的思路半年都没啥进展。[允悲] \u{200B}\u{200B}\u{200B}".to_string(),
             is_long_text: false,
+            long_text: None,
             media_asset: MediaAsset::None,
+            picture_count: 0,
             created_at: FixedOffset::east(8 * 3600)
                 .ymd(2022, 1, 9)
                 .and_hms(11, 50, 55),
+            visibility: PostVisibility::Visible,
+            faved_at: None,
+            unfaved_at: None,
+            sources: vec![PostSource::Favorites],
             retweeted_post: None,
         };
         let s = serde_json::to_string_pretty(&post)?;
@@ -121,6 +270,21 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_post_source() {
+        for source in [
+            PostSource::Favorites,
+            PostSource::Mine,
+            PostSource::Likes,
+            PostSource::User(1773116334),
+        ] {
+            assert_eq!(source.tag().parse::<PostSource>().unwrap(), source);
+        }
+        assert!("user:".parse::<PostSource>().is_err());
+        assert!("user:abc".parse::<PostSource>().is_err());
+        assert!("timeline".parse::<PostSource>().is_err());
+    }
+
     #[test]
     fn test_deserialize_user() -> Result<(), anyhow::Error> {
         let json = r#"{
@@ -1,4 +1,4 @@
-use crate::weibo::post::{MediaAsset, Post, User, VideoEntry};
+use crate::weibo::post::{MediaAsset, Post, PostVisibility, User, VideoEntry};
 use chrono::{DateTime, FixedOffset};
 use regex::Regex;
 use serde::{Deserialize, Deserializer};
@@ -14,6 +14,8 @@ use std::collections::HashMap;
 // 这两个字段，不管是外层还是嵌套的 retweeted_status 中，都可能存在。
 // 发布微博时就上传图片，pic_ids/pic_infos 出现在外层字段中；而转发图片微博，pic_ids/pic_infos 出现在嵌套的 retweeted_status 中。
 // 而是在转发的时候加上一张图片，则该图片仅仅被视为一个 url 而已。其信息不会出现在 pic_ids/pic_infos 中。
+// 图片超过 9 张时，pic_infos 中只有前 9 张的信息，而 pic_ids 中是全部图片的 id，pic_num 是图片的数量。
+// 没有 pic_infos 的图片，根据其 id 拼出 url，见 picture_url_from_id。
 //
 // 2)视频微博。
 // 视频的短链是作为微博内容的一部分，放到 text/text_raw 字段中的。
@@ -46,16 +48,19 @@ use std::collections::HashMap;
 // 其他的一些问题：
 // * 微博内容很长时，isLongText 字段为 true，此时 text/text_raw 中只包含一部分的内容。
 // * 微博某些情况下不可见，返回的字段也不同，包括：
-//   * 被作者删除
-//   * 因作者设置原因而不可见(比如设置「仅半年内可见」的情况)
-//   * 监管原因被夹
-//   需要依次列出来，并正确处理。
+//   * 被作者删除。有 deleted 字段，值为 "1"，text_raw 形如「抱歉，此微博已被作者删除。查看帮助：...」
+//   * 因作者设置原因而不可见(比如设置「仅半年内可见」的情况)。
+//     text_raw 形如「抱歉，作者已设置仅展示半年内微博，此微博已不可见。」，且可能没有 created_at 字段
+//   * 监管原因被夹。text_raw 形如「该账号因被投诉违反法律法规和《微博社区公约》的相关规定，现已无法查看。」
+//   这几种情况下，都没有 user 字段。目前只能根据 deleted 字段和 text_raw 的内容判断，见 detect_visibility。
+//   不可见的微博也会保存下来，其 text_raw 即是上述提示文字。
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct RawPost {
     id: i64,
+    #[serde(default)]
     mblogid: String,
-    user: User,
+    user: Option<User>,
     text_raw: String,
     #[serde(default, rename(deserialize = "isLongText"))]
     is_long_text: bool,
@@ -64,6 +69,8 @@ pub struct RawPost {
     pic_ids: Vec<String>,
     #[serde(default)]
     pic_infos: HashMap<String, PicInfo>,
+    #[serde(default)]
+    pic_num: u32,
 
     #[serde(default, rename(deserialize = "url_struct"))]
     url_structs: Vec<UrlStruct>,
@@ -71,6 +78,7 @@ pub struct RawPost {
 
     #[serde(deserialize_with = "parse_weibo_datetime")]
     pub created_at: DateTime<FixedOffset>,
+    deleted: Option<Value>,
 
     #[serde(rename(deserialize = "retweeted_status"))]
     retweeted_post: Option<RawRetweetedPost>,
@@ -87,9 +95,19 @@ where
     Ok(datetime)
 }
 
+fn parse_optional_weibo_datetime<'de, D>(
+    deserializer: D,
+) -> Result<Option<DateTime<FixedOffset>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    parse_weibo_datetime(deserializer).map(Some)
+}
+
 #[derive(Clone, Debug, Deserialize)]
 struct RawRetweetedPost {
     id: i64,
+    #[serde(default)]
     mblogid: String,
     user: Option<User>,
     text_raw: String,
@@ -100,9 +118,13 @@ struct RawRetweetedPost {
     pic_ids: Vec<String>,
     #[serde(default)]
     pic_infos: HashMap<String, PicInfo>,
+    #[serde(default)]
+    pic_num: u32,
 
-    #[serde(deserialize_with = "parse_weibo_datetime")]
-    pub created_at: DateTime<FixedOffset>,
+    // 不可见的微博可能没有此字段
+    #[serde(default, deserialize_with = "parse_optional_weibo_datetime")]
+    pub created_at: Option<DateTime<FixedOffset>>,
+    deleted: Option<Value>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -157,6 +179,50 @@ impl RawPost {
     }
 }
 
+// 长微博全文接口(/ajax/statuses/longtext)返回的 data 字段
+#[derive(Clone, Debug, Deserialize)]
+pub struct RawLongText {
+    #[serde(default, rename(deserialize = "longTextContent"))]
+    long_text_content: String,
+    #[serde(default, rename(deserialize = "url_struct"))]
+    url_structs: Vec<UrlStruct>,
+}
+
+impl RawLongText {
+    // 全文为空(比如微博已被删除)时返回 None
+    pub fn normalize(mut self) -> Option<String> {
+        if self.long_text_content.is_empty() {
+            return None;
+        }
+        replace_short_urls(&mut self.long_text_content, &self.url_structs, &None);
+        Some(self.long_text_content)
+    }
+}
+
+// 提示文字只在没有 user 字段或者有 deleted 字段时才有意义，正常的微博中也可能引用这些文字
+fn detect_visibility(deleted: &Option<Value>, has_user: bool, text_raw: &str) -> PostVisibility {
+    let deleted = match deleted {
+        Some(Value::String(s)) => s == "1",
+        Some(Value::Number(n)) => n.as_i64() == Some(1),
+        Some(Value::Bool(b)) => *b,
+        _ => false,
+    };
+    if has_user && !deleted {
+        return PostVisibility::Visible;
+    }
+    if deleted || text_raw.starts_with("抱歉，此微博已被作者删除") {
+        PostVisibility::Deleted
+    } else if text_raw.contains("《微博社区公约》") && text_raw.contains("现已无法查看")
+    {
+        PostVisibility::Censored
+    } else if text_raw.starts_with("抱歉，作者已设置") || text_raw.starts_with("抱歉，由于作者设置")
+    {
+        PostVisibility::Restricted
+    } else {
+        PostVisibility::Visible
+    }
+}
+
 fn normalize_raw_post(mut raw_post: RawPost) -> Post {
     let video_entry = replace_short_urls(
         &mut raw_post.text_raw,
@@ -164,14 +230,25 @@ fn normalize_raw_post(mut raw_post: RawPost) -> Post {
         &raw_post.page_info,
     );
 
+    let visibility = detect_visibility(
+        &raw_post.deleted,
+        raw_post.user.is_some(),
+        &raw_post.text_raw,
+    );
     let mut post = Post {
         id: raw_post.id,
         mblogid: raw_post.mblogid,
-        user: raw_post.user,
+        user: raw_post.user.unwrap_or_default(),
         text_raw: raw_post.text_raw,
         is_long_text: raw_post.is_long_text,
+        long_text: None,
         media_asset: MediaAsset::None,
+        picture_count: raw_post.pic_num.max(raw_post.pic_ids.len() as u32),
         created_at: raw_post.created_at,
+        visibility,
+        faved_at: None,
+        unfaved_at: None,
+        sources: vec![],
         retweeted_post: None,
     };
 
@@ -187,6 +264,7 @@ fn normalize_raw_post(mut raw_post: RawPost) -> Post {
             retweeted_post,
             &raw_post.url_structs,
             &raw_post.page_info,
+            raw_post.created_at,
         )));
     }
     post
@@ -196,21 +274,32 @@ fn normalize_raw_retweeted_post(
     mut retweeted_post: RawRetweetedPost,
     url_structs: &[UrlStruct],
     page_info: &Option<PageInfo>,
+    retweeted_at: DateTime<FixedOffset>,
 ) -> Post {
+    let visibility = detect_visibility(
+        &retweeted_post.deleted,
+        retweeted_post.user.is_some(),
+        &retweeted_post.text_raw,
+    );
     let video_entry = replace_short_urls(&mut retweeted_post.text_raw, url_structs, page_info);
 
     let mut post = Post {
         id: retweeted_post.id,
         mblogid: retweeted_post.mblogid,
-        user: if retweeted_post.user.is_some() {
-            retweeted_post.user.unwrap()
-        } else {
-            User::default()
-        },
+        user: retweeted_post.user.unwrap_or_default(),
         text_raw: retweeted_post.text_raw,
         is_long_text: retweeted_post.is_long_text,
+        long_text: None,
         media_asset: MediaAsset::None,
-        created_at: retweeted_post.created_at,
+        picture_count: retweeted_post
+            .pic_num
+            .max(retweeted_post.pic_ids.len() as u32),
+        // 不可见的微博可能没有发布时间，只好以转发的时间代替
+        created_at: retweeted_post.created_at.unwrap_or(retweeted_at),
+        visibility,
+        faved_at: None,
+        unfaved_at: None,
+        sources: vec![],
         retweeted_post: None,
     };
 
@@ -273,8 +362,9 @@ fn collect_picture_asset(
 ) -> Option<MediaAsset> {
     let mut picture_urls = vec![];
     for pic_id in &pic_ids {
-        if let Some(entry) = pic_infos.remove(pic_id) {
-            picture_urls.push(entry.original.url);
+        match pic_infos.remove(pic_id) {
+            Some(entry) => picture_urls.push(entry.original.url),
+            None => picture_urls.push(picture_url_from_id(pic_id)),
         }
     }
     if !picture_urls.is_empty() {
@@ -284,6 +374,16 @@ fn collect_picture_asset(
     }
 }
 
+// 图片 id 的第 22 个字符表示图片格式。wx1 到 wx4 均可访问同样的图片
+fn picture_url_from_id(pic_id: &str) -> String {
+    let ext = match pic_id.chars().nth(21) {
+        Some('g') => "gif",
+        Some('p') => "png",
+        _ => "jpg",
+    };
+    format!("https://wx1.sinaimg.cn/orj1080/{}.{}", pic_id, ext)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -315,6 +415,7 @@ mod tests {
                 .and_hms(11, 50, 55)
         );
         assert!(!post.is_retweet());
+        assert_eq!(post.overall_visibility(), PostVisibility::Visible);
         Ok(())
     }
 
@@ -327,13 +428,83 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_retweeted_deleted_weibo_post() -> Result<(), anyhow::Error> {
+        let raw: RawPost =
+            serde_json::from_str(include_str!("../../test_data/retweet_deleted.json"))?;
+        let post = raw.normalize();
+        assert!(post.is_valid());
+        assert!(post.is_visible());
+        assert_eq!(post.overall_visibility(), PostVisibility::Deleted);
+
+        let retweeted_post = post.retweeted_post.unwrap();
+        assert_eq!(retweeted_post.visibility, PostVisibility::Deleted);
+        assert_eq!(retweeted_post.user, User::default());
+        assert!(retweeted_post
+            .text_raw
+            .starts_with("抱歉，此微博已被作者删除"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_retweeted_restricted_weibo_post() -> Result<(), anyhow::Error> {
+        let raw: RawPost =
+            serde_json::from_str(include_str!("../../test_data/retweet_half_year.json"))?;
+        let post = raw.normalize();
+        assert!(post.is_valid());
+
+        let retweeted_post = post.retweeted_post.as_ref().unwrap();
+        assert_eq!(retweeted_post.visibility, PostVisibility::Restricted);
+        // 没有发布时间，以转发时间代替
+        assert_eq!(retweeted_post.created_at, post.created_at);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_retweeted_censored_weibo_post() -> Result<(), anyhow::Error> {
+        let raw: RawPost =
+            serde_json::from_str(include_str!("../../test_data/retweet_censored.json"))?;
+        let post = raw.normalize();
+        assert!(post.is_valid());
+        assert_eq!(post.overall_visibility(), PostVisibility::Censored);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_post_quoting_notice() -> Result<(), anyhow::Error> {
+        let mut raw: Value = serde_json::from_str(include_str!("../../test_data/text.json"))?;
+        raw["text_raw"] = Value::String(
+            "转发的微博提示「该账号因被投诉违反法律法规和《微博社区公约》的相关规定，现已无法查看。」"
+                .to_string(),
+        );
+        let post = serde_json::from_value::<RawPost>(raw)?.normalize();
+        assert_eq!(post.visibility, PostVisibility::Visible);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_long_text() -> Result<(), anyhow::Error> {
+        let res: serde_json::Value =
+            serde_json::from_str(include_str!("../../test_data/long_text.json"))?;
+        let raw: RawLongText = serde_json::from_value(res["data"].clone())?;
+        let long_text = raw.normalize().unwrap();
+        assert!(long_text.contains("https://moxie.org/2022/01/07/web3-first-impressions.html"));
+        assert!(!long_text.contains("http://t.cn/"));
+        assert!(long_text.ends_with("值得一读。"));
+
+        let raw: RawLongText = serde_json::from_str("{}")?;
+        assert_eq!(raw.normalize(), None);
+        Ok(())
+    }
+
     #[test]
     fn test_parse_picture_weibo_post() -> Result<(), anyhow::Error> {
         let raw: RawPost = serde_json::from_str(include_str!("../../test_data/picture.json"))?;
         let post = raw.normalize();
 
         // 此微博中的图片数量为 18，但 pic_infos 中只列出了前 9 张的信息
-        // 看来，当图片数量超过 9 张，就会如此。
+        // 看来，当图片数量超过 9 张，就会如此。其余的图片根据 pic_ids 拼出 url
+        assert_eq!(post.picture_count, 18);
         assert_eq!(
             post.media_asset,
             MediaAsset::Pictures(vec![
@@ -346,6 +517,15 @@ mod tests {
                 "https://wx3.sinaimg.cn/orj1080/663aa05aly1gy4bls69eij20hr0chq30.jpg".to_string(),
                 "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4bls9eu2j20hr0ch74c.jpg".to_string(),
                 "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4blsbr3sj20hr0chgmh.jpg".to_string(),
+                "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4bls7t3wj20hr0ch753.jpg".to_string(),
+                "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4blsa3hxj213z0nuwgq.jpg".to_string(),
+                "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4blsb7kdj213z0nu0uw.jpg".to_string(),
+                "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4blsbao9j213y0nugnk.jpg".to_string(),
+                "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4blsc6acj212w0boaaz.jpg".to_string(),
+                "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4blsdq2bj212w0bojsv.jpg".to_string(),
+                "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4blsdxfpj212w0bozlk.jpg".to_string(),
+                "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4blsdusbj212w0boabc.jpg".to_string(),
+                "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4blseixlj212w0bo0u9.jpg".to_string(),
             ])
         );
         assert!(!post.is_retweet());
@@ -358,9 +538,16 @@ mod tests {
             serde_json::from_str(include_str!("../../test_data/picture_retweet.json"))?;
         let post = raw.normalize();
         assert!(post.is_retweet());
+        let retweeted_post = post.retweeted_post.unwrap();
+        assert_eq!(retweeted_post.picture_count, 18);
+        let picture_urls = match retweeted_post.media_asset {
+            MediaAsset::Pictures(picture_urls) => picture_urls,
+            _ => panic!("not a picture post"),
+        };
+        assert_eq!(picture_urls.len(), 18);
         assert_eq!(
-            post.retweeted_post.unwrap().media_asset,
-            MediaAsset::Pictures(vec![
+            picture_urls[..9],
+            vec![
                 "https://wx4.sinaimg.cn/orj1080/663aa05aly1gy4bls1n7zj20zk0npgo2.jpg".to_string(),
                 "https://wx4.sinaimg.cn/orj1080/663aa05aly1gy4bls1c4mg20k00j61kx.gif".to_string(),
                 "https://wx3.sinaimg.cn/orj1080/663aa05aly1gy4bls1f3zj20hr0chgm4.jpg".to_string(),
@@ -370,7 +557,7 @@ mod tests {
                 "https://wx3.sinaimg.cn/orj1080/663aa05aly1gy4bls69eij20hr0chq30.jpg".to_string(),
                 "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4bls9eu2j20hr0ch74c.jpg".to_string(),
                 "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4blsbr3sj20hr0chgmh.jpg".to_string(),
-            ])
+            ]
         );
         Ok(())
     }
@@ -0,0 +1,71 @@
+-- weise 最初的数据库结构（schema 版本 1），微博以 JSON 格式保存在 post.content 中
+create table post (
+    id integer primary key,
+    url text not null,
+    content text not null,
+    faved integer not null default 0
+);
+
+create table post_tombstone (
+    id integer primary key,
+    url text not null
+);
+
+create table settings (
+    name text unique,
+    value blob not null
+);
+
+insert into post_tombstone (id, url) values (4723695598438753, 'https://weibo.com/1773116334/L9WqHzpiV');
+insert into settings (name, value) values ('max_page', 42);
+
+insert into post (id, url, content, faved) values (4723695598438753, 'https://weibo.com/1773116334/L9WqHzpiV', '{
+  "id": 4723695598438753,
+  "mblogid": "L9WqHzpiV",
+  "user": {
+    "id": 1773116334,
+    "screen_name": "zhh-4096"
+  },
+  "text_raw": "今年我一定会开一家新公司以 GraalVM 为工具研发几个产品，目前产品思路逐渐清晰，长中短期都有，不会再像过去十年研究数据库那么耗时了，搞数据库基础理论创新实在是太硬核了，没有好的思路半年都没啥进展。[允悲] ​​​",
+  "is_long_text": false,
+  "media_asset": "None",
+  "created_at": "2022-01-09T11:50:55+08:00",
+  "retweeted_post": null
+}', 1);
+insert into post (id, url, content, faved) values (4723307730175522, 'https://weibo.com/2180010815/L9Ml70JF0', '{
+  "id": 4723307730175522,
+  "mblogid": "L9Ml70JF0",
+  "user": {
+    "id": 2180010815,
+    "screen_name": "喜欢默默小时候"
+  },
+  "text_raw": "转发微博",
+  "is_long_text": false,
+  "media_asset": "None",
+  "created_at": "2022-01-08T10:09:40+08:00",
+  "retweeted_post": {
+    "id": 4723131660373685,
+    "mblogid": "L9HL81zdb",
+    "user": {
+      "id": 1715118170,
+      "screen_name": "网路冷眼"
+    },
+    "text_raw": "【How JPEG Compression Works】https://medium.com/geekculture/how-jpeg-compression-works-a751cd877c8c JPEG 压缩的工作原理：解释 JPEG 压缩背后的神奇步骤  。 ​​​",
+    "is_long_text": true,
+    "media_asset": {
+      "Pictures": [
+        "https://wx4.sinaimg.cn/orj1080/663aa05aly1gy4bls1n7zj20zk0npgo2.jpg",
+        "https://wx4.sinaimg.cn/orj1080/663aa05aly1gy4bls1c4mg20k00j61kx.gif",
+        "https://wx3.sinaimg.cn/orj1080/663aa05aly1gy4bls1f3zj20hr0chgm4.jpg",
+        "https://wx3.sinaimg.cn/orj1080/663aa05aly1gy4bls3v67j20hr0chgmf.jpg",
+        "https://wx3.sinaimg.cn/orj1080/663aa05aly1gy4bls1zq7j20hr0chglu.jpg",
+        "https://wx4.sinaimg.cn/orj1080/663aa05aly1gy4bls4r0kj20hr0chdgc.jpg",
+        "https://wx3.sinaimg.cn/orj1080/663aa05aly1gy4bls69eij20hr0chq30.jpg",
+        "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4bls9eu2j20hr0ch74c.jpg",
+        "https://wx1.sinaimg.cn/orj1080/663aa05aly1gy4blsbr3sj20hr0chgmh.jpg"
+      ]
+    },
+    "created_at": "2022-01-07T22:30:02+08:00",
+    "retweeted_post": null
+  }
+}', 1);